@@ -2,15 +2,21 @@
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
+pub use self::capital_converter::CapitalConverter;
 use ink_lang as ink;
 
 #[ink::contract]
 mod capital_converter {
     use erc20::Erc20;
     use ink_env::call::FromAccountId;
-    use ink_prelude::string::String;
+    use ink_prelude::{string::String, vec, vec::Vec};
+    use ink_storage::traits::{PackedLayout, SpreadLayout};
+
+    #[cfg(not(feature = "ink-as-dependency"))]
     use ink_storage::{collections::HashMap as StorageHashMap, lazy::Lazy};
     use primitive_types::U256;
+    #[cfg(not(test))]
+    use strategy::StrategyRef;
 
     #[ink(event)]
     pub struct Mint {
@@ -34,24 +40,284 @@ mod capital_converter {
 
     #[ink(event)]
     pub struct Payouts {
+        #[ink(topic)]
+        claim_id: u64,
         #[ink(topic)]
         to: Option<AccountId>,
         #[ink(topic)]
         amount: Balance,
     }
 
+    #[ink(event)]
+    pub struct PayoutProposed {
+        #[ink(topic)]
+        claim_id: u64,
+        #[ink(topic)]
+        to: AccountId,
+        #[ink(topic)]
+        amount: Balance,
+    }
+
+    #[ink(event)]
+    pub struct PayoutApproved {
+        #[ink(topic)]
+        claim_id: u64,
+        #[ink(topic)]
+        approver: AccountId,
+    }
+
+    #[ink(event)]
+    pub struct PayoutCancelled {
+        #[ink(topic)]
+        claim_id: u64,
+    }
+
+    #[ink(event)]
+    pub struct LossRecorded {
+        #[ink(topic)]
+        claim_id: u64,
+        #[ink(topic)]
+        assets_paid: Balance,
+        #[ink(topic)]
+        rate_before: u128,
+        #[ink(topic)]
+        rate_after: u128,
+    }
+
+    #[ink(event)]
+    pub struct SetApprover {
+        #[ink(topic)]
+        approver: AccountId,
+        #[ink(topic)]
+        enabled: bool,
+    }
+
+    #[ink(event)]
+    pub struct SetPayoutCap {
+        #[ink(topic)]
+        cap: u128,
+    }
+
+    #[ink(event)]
+    pub struct SetLargePayout {
+        #[ink(topic)]
+        threshold: Balance,
+    }
+
+    #[ink(event)]
+    pub struct SetEpochLength {
+        #[ink(topic)]
+        blocks: BlockNumber,
+    }
+
+    #[ink(event)]
+    pub struct EmergencyExit {
+        #[ink(topic)]
+        sender: AccountId,
+        #[ink(topic)]
+        amount: Balance,
+        #[ink(topic)]
+        output: Balance,
+    }
+
+    #[ink(event)]
+    pub struct Shutdown {
+        #[ink(topic)]
+        guardian: AccountId,
+        assets: Balance,
+        supply: Balance,
+    }
+
+    #[ink(event)]
+    pub struct SetGuardian {
+        #[ink(topic)]
+        guardian: AccountId,
+    }
+
+    #[ink(event)]
+    pub struct SetLockBlocks {
+        #[ink(topic)]
+        blocks: BlockNumber,
+    }
+
+    #[ink(event)]
+    pub struct AddStrategy {
+        #[ink(topic)]
+        strategy: AccountId,
+        #[ink(topic)]
+        debt_ceiling: Balance,
+    }
+
+    #[ink(event)]
+    pub struct SetDebtCeiling {
+        #[ink(topic)]
+        strategy: AccountId,
+        #[ink(topic)]
+        debt_ceiling: Balance,
+    }
+
+    #[ink(event)]
+    pub struct SetMaxAllocation {
+        #[ink(topic)]
+        max: u128,
+    }
+
+    #[ink(event)]
+    pub struct Allocate {
+        #[ink(topic)]
+        strategy: AccountId,
+        #[ink(topic)]
+        amount: Balance,
+    }
+
+    #[ink(event)]
+    pub struct Deallocate {
+        #[ink(topic)]
+        strategy: AccountId,
+        #[ink(topic)]
+        amount: Balance,
+    }
+
+    #[ink(event)]
+    pub struct Harvest {
+        #[ink(topic)]
+        strategy: AccountId,
+        #[ink(topic)]
+        profit: Balance,
+    }
+
+    #[ink(event)]
+    pub struct ReportProfit {
+        #[ink(topic)]
+        amount: Balance,
+        #[ink(topic)]
+        locked: Balance,
+    }
+
+    #[ink(event)]
+    pub struct SetReporter {
+        #[ink(topic)]
+        reporter: AccountId,
+    }
+
+    #[ink(event)]
+    pub struct SetProfitUnlock {
+        #[ink(topic)]
+        blocks: BlockNumber,
+    }
+
+    #[ink(event)]
+    pub struct IouIssued {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        owner: AccountId,
+        #[ink(topic)]
+        amount: Balance,
+    }
+
+    #[ink(event)]
+    pub struct IouSettled {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        owner: AccountId,
+        #[ink(topic)]
+        amount: Balance,
+    }
+
+    #[ink(event)]
+    pub struct IouTransfer {
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        from: AccountId,
+        #[ink(topic)]
+        to: AccountId,
+    }
+
     #[ink(event)]
     pub struct SetOperator {
         #[ink(topic)]
         operator: AccountId,
     }
 
+    #[ink(event)]
+    pub struct SetZapper {
+        #[ink(topic)]
+        zapper: AccountId,
+    }
+
     #[ink(event)]
     pub struct SetMaxConvert {
         #[ink(topic)]
         max: Balance,
     }
 
+    #[ink(event)]
+    pub struct FeeCharged {
+        #[ink(topic)]
+        payer: AccountId,
+        #[ink(topic)]
+        amount: Balance,
+        // whether the fee was taken in nDot rather than the underlying token
+        in_shares: bool,
+    }
+
+    #[ink(event)]
+    pub struct SetFees {
+        #[ink(topic)]
+        convert_fee: u128,
+        #[ink(topic)]
+        exit_fee: u128,
+    }
+
+    #[ink(event)]
+    pub struct SetTreasury {
+        #[ink(topic)]
+        treasury: AccountId,
+    }
+
+    #[ink(event)]
+    pub struct SetDepositLimits {
+        #[ink(topic)]
+        max_total_assets: Balance,
+        #[ink(topic)]
+        max_per_account: Balance,
+    }
+
+    #[ink(event)]
+    pub struct SetAllowlistEnabled {
+        #[ink(topic)]
+        enabled: bool,
+    }
+
+    #[ink(event)]
+    pub struct SetAllowlisted {
+        #[ink(topic)]
+        account: AccountId,
+        #[ink(topic)]
+        allowed: bool,
+    }
+
+    #[ink(event)]
+    pub struct SetCover {
+        #[ink(topic)]
+        cover: AccountId,
+    }
+
+    #[ink(event)]
+    pub struct SetLockedCapital {
+        #[ink(topic)]
+        amount: Balance,
+    }
+
+    #[ink(event)]
+    pub struct SetMcrRatio {
+        #[ink(topic)]
+        ratio: u128,
+    }
+
     /// Event emitted when a token transfer occurs.
     #[ink(event)]
     pub struct Transfer {
@@ -82,11 +348,130 @@ mod capital_converter {
         InsufficientSupply,
         /// Returned if not enough allowance to fulfill a request is available.
         InsufficientAllowance,
+        /// Returned if a single convert is above `max_convert`.
+        ExceedsMaxConvert,
+        /// Returned if a convert would take the pool above `max_total_assets`.
+        ExceedsMaxTotalAssets,
+        /// Returned if a convert would take the account above `max_per_account`.
+        ExceedsMaxPerAccount,
+        /// Returned if the allowlist is enabled and the account is not on it.
+        NotAllowlisted,
+        /// Returned if the shares are still within the flash loan lock.
+        SharesLocked,
+        /// Returned if an exit would take the pool below the minimum capital requirement.
+        ExceedsFreeCapital,
     }
 
     /// The ERC-20 result type.
     pub type Result<T> = core::result::Result<T, Error>;
 
+    // A payout of pooled capital for a claim.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct PayoutRecord {
+        pub to: AccountId,
+        pub amount: Balance,
+        // operator who requested the payout
+        pub operator: AccountId,
+        // second approver of a large payout, zero if none was needed
+        pub approver: AccountId,
+        // block the payout was requested, or executed once paid
+        pub block: BlockNumber,
+    }
+
+    // Balance of an account, or the total supply, from `block` on.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct Checkpoint {
+        pub block: BlockNumber,
+        pub balance: Balance,
+        // sum of balance * blocks held up to `block`
+        pub cumulative: u128,
+    }
+
+    // nDot of an account that cannot be redeemed before `unlock_at`.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct ShareLock {
+        pub amount: Balance,
+        pub unlock_at: BlockNumber,
+    }
+
+    // Capital a strategy may hold and currently holds.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct StrategyParams {
+        pub debt_ceiling: Balance,
+        // allocated and not yet deallocated
+        pub debt: Balance,
+    }
+
+    // Exchange rate observed at `block`.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct Observation {
+        pub block: BlockNumber,
+        // assets per share, scaled by `RATE_PRECISION`
+        pub rate: u128,
+        // sum of rate * blocks up to `block`
+        pub rate_cumulative: u128,
+    }
+
+    // Underlying token owed to `owner` for an exit that could not be paid in full.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct Iou {
+        pub owner: AccountId,
+        pub amount: Balance,
+    }
+
+    // Drawdown of the pool by a claim payout.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct Loss {
+        pub claim_id: u64,
+        pub assets_paid: Balance,
+        // exchange rates around the payout, scaled by `RATE_PRECISION`
+        pub rate_before: u128,
+        pub rate_after: u128,
+        pub block: BlockNumber,
+    }
+
     /// Defines the storage of your contract.
     /// Add new fields to the below struct in order
     /// to add new static storage fields to your contract.
@@ -107,17 +492,106 @@ mod capital_converter {
         /// Decimals of the token
         decimals: u8,
 
-        // mock address to indicate DOT, like ETH: 0xEeeeeEeeeEeEeeEeEeEeeEEEeeeeEeeeeeeeEEeE
-        dot: AccountId,
         max_convert: Balance,
         token: AccountId,
         token_contract: Lazy<Erc20>,
         operator: AccountId,
-        // in case of flashloan attacks
-        deposit_at: StorageHashMap<AccountId, BlockNumber>,
+        // the zap router, converts and exits for its users with their deposit limits
+        zapper: AccountId,
+        // in case of flashloan attacks, newly minted nDot stays locked for `lock_blocks`,
+        // also after being transferred
+        locks: StorageHashMap<AccountId, ShareLock>,
+        lock_blocks: BlockNumber,
         owner: AccountId,
+        // cover/capacity contract allowed to report the capital locked by active policies
+        cover: AccountId,
+        // capital backing active insurance risk
+        locked_capital: Balance,
+        // minimum capital requirement as a ratio of locked capital, in basis points
+        mcr_ratio: u128,
+        // executed payouts by claim id
+        payout_records: StorageHashMap<u64, PayoutRecord>,
+        // large payouts waiting for a second approver
+        pending_payouts: StorageHashMap<u64, PayoutRecord>,
+        approvers: StorageHashMap<AccountId, bool>,
+        // payouts from this amount on need a second approver
+        large_payout: Balance,
+        // max payouts per epoch, in basis points of the capital
+        payout_cap: u128,
+        epoch_length: BlockNumber,
+        // total paid out in each epoch
+        epoch_payouts: StorageHashMap<u32, Balance>,
+        // fee on convert in basis points, taken in the underlying token
+        convert_fee_bps: u128,
+        // fee on exit in basis points, taken in nDot
+        exit_fee_bps: u128,
+        treasury: AccountId,
+        // max underlying token held by the pool
+        max_total_assets: Balance,
+        // max underlying token one account can convert, net of its exits
+        max_per_account: Balance,
+        // underlying token converted for each account net of its exits, kept apart from
+        // the nDot balance so moving nDot to another account doesn't reset the cap
+        net_deposits: StorageHashMap<AccountId, Balance>,
+        // only allowlisted accounts can convert while enabled
+        allowlist_enabled: bool,
+        allowlist: StorageHashMap<AccountId, bool>,
+        // balance-time accumulators, indexed by account and checkpoint number
+        checkpoints: StorageHashMap<(AccountId, u32), Checkpoint>,
+        num_checkpoints: StorageHashMap<AccountId, u32>,
+        supply_checkpoints: StorageHashMap<u32, Checkpoint>,
+        num_supply_checkpoints: u32,
+        // can shut the pool down in an emergency
+        guardian: AccountId,
+        shutdown: bool,
+        // exchange rate frozen at shutdown, as assets over supply
+        shutdown_assets: Balance,
+        shutdown_supply: Balance,
+        post_mortem: Option<String>,
+        // strategy contracts idle capital can be allocated to, in withdrawal order
+        strategies: Vec<AccountId>,
+        strategy_params: StorageHashMap<AccountId, StrategyParams>,
+        total_debt: Balance,
+        // max share of the capital allocated to strategies in basis points,
+        // the rest is kept as liquidity buffer for `exit` and `payouts`
+        max_allocation: u128,
+        // reports premiums and other profit sent to the pool
+        reporter: AccountId,
+        // profit still locked right after the last report, unlocks linearly
+        // over `profit_unlock` blocks so the exchange rate cannot be sandwiched
+        locked_profit: Balance,
+        last_report: BlockNumber,
+        profit_unlock: BlockNumber,
+        // ring buffer of exchange rate observations, at most one per block
+        observations: StorageHashMap<u32, Observation>,
+        observation_index: u32,
+        observation_count: u32,
+        // IOUs settled first-in-first-out, from `iou_head` up to `iou_tail`
+        ious: StorageHashMap<u64, Iou>,
+        iou_head: u64,
+        iou_tail: u64,
+        // outstanding IOUs, by account and in total
+        iou_balances: StorageHashMap<AccountId, Balance>,
+        iou_total: Balance,
+        // ledger of payout drawdowns, in the order they were paid
+        losses: StorageHashMap<u64, Loss>,
+        loss_count: u64,
+        total_loss: Balance,
+        // value lost by the nDot holders to the payouts of each epoch
+        epoch_losses: StorageHashMap<u32, Balance>,
     }
 
+    /// Denominator of ratios expressed in basis points.
+    pub const BPS_DENOMINATOR: u128 = 10000;
+    /// Hard cap on the convert and exit fees, 5%.
+    pub const MAX_FEE_BPS: u128 = 500;
+    /// Hard cap on the flash loan lock of newly minted nDot, about one day of 6s blocks.
+    pub const MAX_LOCK_BLOCKS: BlockNumber = 14400;
+    /// Scale of the exchange rate.
+    pub const RATE_PRECISION: u128 = 1_000_000_000_000_000_000;
+    /// Size of the exchange rate observation ring buffer.
+    pub const OBSERVATION_CARDINALITY: u32 = 128;
+
     impl CapitalConverter {
         /// Constructor that initializes the `bool` value to the given `init_value`.
         #[ink(constructor)]
@@ -137,12 +611,62 @@ mod capital_converter {
                 symbol,
                 decimals,
                 owner: caller,
-                dot: AccountId::from([0xdd; 32]),
                 max_convert: 10000 * 10u128.saturating_pow(decimals as u32),
                 token,
                 token_contract: Lazy::new(token_contract),
                 operator: Default::default(),
-                deposit_at: StorageHashMap::new(),
+                zapper: Default::default(),
+                locks: StorageHashMap::new(),
+                lock_blocks: 1,
+                cover: Default::default(),
+                locked_capital: 0,
+                mcr_ratio: BPS_DENOMINATOR,
+                payout_records: StorageHashMap::new(),
+                pending_payouts: StorageHashMap::new(),
+                approvers: StorageHashMap::new(),
+                large_payout: Balance::MAX,
+                payout_cap: BPS_DENOMINATOR,
+                // about one day of 6s blocks
+                epoch_length: 14400,
+                epoch_payouts: StorageHashMap::new(),
+                convert_fee_bps: 0,
+                exit_fee_bps: 0,
+                treasury: Default::default(),
+                max_total_assets: Balance::MAX,
+                max_per_account: Balance::MAX,
+                net_deposits: StorageHashMap::new(),
+                allowlist_enabled: false,
+                allowlist: StorageHashMap::new(),
+                checkpoints: StorageHashMap::new(),
+                num_checkpoints: StorageHashMap::new(),
+                supply_checkpoints: StorageHashMap::new(),
+                num_supply_checkpoints: 0,
+                guardian: Default::default(),
+                shutdown: false,
+                shutdown_assets: 0,
+                shutdown_supply: 0,
+                post_mortem: None,
+                strategies: vec![],
+                strategy_params: StorageHashMap::new(),
+                total_debt: 0,
+                max_allocation: 0,
+                reporter: Default::default(),
+                locked_profit: 0,
+                last_report: 0,
+                // about one day of 6s blocks
+                profit_unlock: 14400,
+                observations: StorageHashMap::new(),
+                observation_index: 0,
+                observation_count: 0,
+                ious: StorageHashMap::new(),
+                iou_head: 0,
+                iou_tail: 0,
+                iou_balances: StorageHashMap::new(),
+                iou_total: 0,
+                losses: StorageHashMap::new(),
+                loss_count: 0,
+                total_loss: 0,
+                epoch_losses: StorageHashMap::new(),
             };
             instance
         }
@@ -171,12 +695,6 @@ mod capital_converter {
             self.decimals
         }
 
-        /// Wrapped mock Dot Address
-        #[ink(message)]
-        pub fn dot_account_id(&self) -> AccountId {
-            self.dot
-        }
-
         /// Returns the account balance for the specified `owner`.
         ///
         /// Returns `0` if the account is non-existent.
@@ -263,6 +781,8 @@ mod capital_converter {
             let user_balance = self.balance_of(user);
             self.balances.insert(user, user_balance + amount);
             *self.total_supply += amount;
+            self.write_checkpoint(user);
+            self.write_supply_checkpoint();
             self.env().emit_event(Transfer {
                 from: Some(Default::default()),
                 to: Some(user),
@@ -282,6 +802,8 @@ mod capital_converter {
 
             self.balances.insert(user, user_balance - amount);
             *self.total_supply -= amount;
+            self.write_checkpoint(user);
+            self.write_supply_checkpoint();
             self.env().emit_event(Transfer {
                 from: Some(user),
                 to: Some(Default::default()),
@@ -311,6 +833,24 @@ mod capital_converter {
             self.balances.insert(from, from_balance - value);
             let to_balance = self.balance_of(to);
             self.balances.insert(to, to_balance + value);
+            self.write_checkpoint(from);
+            self.write_checkpoint(to);
+
+            // unlocked nDot is sent first, whatever is left of `value` carries the lock along
+            let lock = self.active_lock(from);
+            let moved = value
+                .saturating_sub(from_balance.saturating_sub(lock.amount))
+                .min(lock.amount);
+            if moved > 0 {
+                self.locks.insert(
+                    from,
+                    ShareLock {
+                        amount: lock.amount - moved,
+                        unlock_at: lock.unlock_at,
+                    },
+                );
+                self.lock_shares(to, moved, lock.unlock_at);
+            }
             self.env().emit_event(Transfer {
                 from: Some(from),
                 to: Some(to),
@@ -319,206 +859,2040 @@ mod capital_converter {
             Ok(())
         }
 
+        /// Returns the sum of `account`'s balance times blocks held, up to `block`.
         #[ink(message)]
-        pub fn smart_balance(&self) -> Balance {
-            if self.token == self.dot {
-                return self.env().balance();
-            }
+        pub fn cumulative_balance(&self, account: AccountId, block: BlockNumber) -> u128 {
+            assert!(block <= self.env().block_number(), "block not yet mined");
+            let count = self.num_checkpoints.get(&account).copied().unwrap_or(0);
+            let checkpoint =
+                Self::find_checkpoint(count, block, |i| self.checkpoints.get(&(account, i)).copied());
+            Self::cumulative_at(checkpoint, block)
+        }
 
-            return self.token_contract.balance_of(self.env().account_id());
+        /// Returns the sum of the total supply times blocks, up to `block`.
+        #[ink(message)]
+        pub fn cumulative_total_supply(&self, block: BlockNumber) -> u128 {
+            assert!(block <= self.env().block_number(), "block not yet mined");
+            let checkpoint = Self::find_checkpoint(self.num_supply_checkpoints, block, |i| {
+                self.supply_checkpoints.get(&i).copied()
+            });
+            Self::cumulative_at(checkpoint, block)
         }
 
-        fn calculate_mint_amount(&self, deposit_amount: Balance) -> Balance {
-            let da: U256 = deposit_amount.into();
-            if self.total_supply() == 0 {
-                let decimal = 10u128.saturating_pow(self.token_decimals() as u32);
-                let dc: U256 = decimal.into();
-                let decimals;
-                if self.token == self.dot {
-                    decimals = 10u128.saturating_pow(10);
-                } else {
-                    decimals =
-                        10u128.saturating_pow(self.token_contract.token_decimals().unwrap() as u32);
-                }
-                let td: U256 = decimals.into();
-                let value: U256 = da * dc / td;
-                return value.as_u128();
-            }
-            let total_supply = self.total_supply();
-            let ts: U256 = total_supply.into();
-            let initial_balance = self.smart_balance().saturating_sub(deposit_amount);
-            let ib: U256 = initial_balance.into();
-            let value = da * ts / ib;
-            return value.as_u128();
+        /// Returns `account`'s average balance between `from_block` and `to_block`.
+        #[ink(message)]
+        pub fn time_weighted_balance(
+            &self,
+            account: AccountId,
+            from_block: BlockNumber,
+            to_block: BlockNumber,
+        ) -> Balance {
+            assert!(from_block < to_block, "invalid block range");
+            let cumulative = self.cumulative_balance(account, to_block)
+                - self.cumulative_balance(account, from_block);
+            cumulative / (to_block - from_block) as u128
         }
 
-        // convert ETH or USDx to nETH/nUSDx
-        #[ink(message, payable)]
-        pub fn convert(&mut self, amount: Balance) {
-            assert!(amount > 0, "CapitalConverter: Cannot stake 0.");
-            assert!(amount <= self.max_convert, "exceeding the maximum limit");
-            let caller = self.env().caller();
-            let block_number = self.env().block_number();
-            let value = self.env().transferred_balance();
-            self.deposit_at.insert(caller, block_number);
+        /// Returns the average total supply between `from_block` and `to_block`.
+        #[ink(message)]
+        pub fn time_weighted_total_supply(
+            &self,
+            from_block: BlockNumber,
+            to_block: BlockNumber,
+        ) -> Balance {
+            assert!(from_block < to_block, "invalid block range");
+            let cumulative =
+                self.cumulative_total_supply(to_block) - self.cumulative_total_supply(from_block);
+            cumulative / (to_block - from_block) as u128
+        }
 
-            if self.token != self.dot {
-                assert!(
-                    value == 0,
-                    "CapitalConverter: Should not allow ETH deposits."
-                );
-                assert!(
-                    self.token_contract
-                        .transfer_from(caller, Self::env().account_id(), amount)
-                        .is_ok(),
-                    "transfer_from operation did not succeed"
-                );
+        fn write_checkpoint(&mut self, account: AccountId) {
+            let block = self.env().block_number();
+            let count = self.num_checkpoints.get(&account).copied().unwrap_or(0);
+            let last = match count {
+                0 => None,
+                _ => self.checkpoints.get(&(account, count - 1)).copied(),
+            };
+            let checkpoint = Self::next_checkpoint(last, block, self.balance_of(account));
+            // several changes in one block share the same checkpoint
+            if last.map(|cp| cp.block) == Some(block) {
+                self.checkpoints.insert((account, count - 1), checkpoint);
             } else {
-                assert!(amount == value, "CapitalConverter: Incorrect eth amount.");
+                self.checkpoints.insert((account, count), checkpoint);
+                self.num_checkpoints.insert(account, count + 1);
             }
+        }
 
-            let value = self.calculate_mint_amount(amount);
-            assert!(
-                self.mint(caller, value).is_ok(),
-                "mint operation did not succeed"
-            );
+        fn write_supply_checkpoint(&mut self) {
+            let block = self.env().block_number();
+            let count = self.num_supply_checkpoints;
+            let last = match count {
+                0 => None,
+                _ => self.supply_checkpoints.get(&(count - 1)).copied(),
+            };
+            let checkpoint = Self::next_checkpoint(last, block, self.total_supply());
+            if last.map(|cp| cp.block) == Some(block) {
+                self.supply_checkpoints.insert(count - 1, checkpoint);
+            } else {
+                self.supply_checkpoints.insert(count, checkpoint);
+                self.num_supply_checkpoints = count + 1;
+            }
+        }
 
-            self.env().emit_event(Mint {
-                sender: Some(caller),
-                input: amount,
-                amount: value,
-            });
+        fn next_checkpoint(
+            last: Option<Checkpoint>,
+            block: BlockNumber,
+            balance: Balance,
+        ) -> Checkpoint {
+            Checkpoint {
+                block,
+                balance,
+                cumulative: Self::cumulative_at(last, block),
+            }
         }
 
-        #[ink(message)]
-        pub  fn show_token(&self) ->AccountId {
-            self.token
+        fn cumulative_at(checkpoint: Option<Checkpoint>, block: BlockNumber) -> u128 {
+            match checkpoint {
+                Some(cp) => cp.cumulative + cp.balance * (block - cp.block) as u128,
+                None => 0,
+            }
+        }
+
+        // binary search for the last of `count` checkpoints at or before `block`
+        fn find_checkpoint<F>(count: u32, block: BlockNumber, get: F) -> Option<Checkpoint>
+        where
+            F: Fn(u32) -> Option<Checkpoint>,
+        {
+            let mut low = 0;
+            let mut high = count;
+            while low < high {
+                let mid = (low + high) / 2;
+                match get(mid) {
+                    Some(cp) if cp.block <= block => low = mid + 1,
+                    _ => high = mid,
+                }
+            }
+            match low {
+                0 => None,
+                _ => get(low - 1),
+            }
         }
 
-        // withdraw the ETH or USDx
+        /// Returns the capital of the pool, held by the converter or its strategies.
         #[ink(message)]
-        pub fn exit(&mut self, amount: Balance) {
-            let caller = self.env().caller();
+        pub fn smart_balance(&self) -> Balance {
+            let mut balance = self.idle_balance();
+            for strategy in self.strategies.iter() {
+                balance += self.strategy_total_assets(*strategy);
+            }
+            balance
+        }
 
-            assert!(
-                self.balance_of(caller) >= amount && amount > 0,
-                "CapitalConverter: insufficient assets"
-            );
+        /// Returns the capital backing nDot, leaving out the profit still locked.
+        #[ink(message)]
+        pub fn unlocked_assets(&self) -> Balance {
+            self.smart_balance()
+                .saturating_sub(self.iou_total)
+                .saturating_sub(self.locked_profit())
+        }
+
+        /// Returns the reported profit still unlocking.
+        #[ink(message)]
+        pub fn locked_profit(&self) -> Balance {
+            let elapsed = self.env().block_number() - self.last_report;
+            if elapsed >= self.profit_unlock {
+                return 0;
+            }
+            let locked: U256 = self.locked_profit.into();
+            let remaining: U256 = (self.profit_unlock - elapsed).into();
+            let unlock: U256 = self.profit_unlock.into();
+            (locked * remaining / unlock).as_u128()
+        }
+
+        /// Reports `amount` of profit that was sent to the pool, to be unlocked
+        /// over `profit_unlock` blocks.
+        #[ink(message)]
+        pub fn report_profit(&mut self, amount: Balance) {
+            assert!(self.env().caller() == self.reporter, "not reporter");
+            self.lock_profit(amount);
+        }
+
+        #[ink(message)]
+        pub fn set_reporter(&mut self, reporter: AccountId) {
+            self.only_owner();
+            assert!(reporter != Default::default(), "reporter is zero");
+            self.reporter = reporter;
+            self.env().emit_event(SetReporter { reporter });
+        }
+
+        #[ink(message)]
+        pub fn set_profit_unlock(&mut self, blocks: BlockNumber) {
+            self.only_owner();
+            // the profit still locked unlocks over the new period from now
+            self.locked_profit = self.locked_profit();
+            self.last_report = self.env().block_number();
+            self.profit_unlock = blocks;
+            self.env().emit_event(SetProfitUnlock { blocks });
+        }
+
+        fn lock_profit(&mut self, amount: Balance) {
+            let locked = self.locked_profit() + amount;
+            self.locked_profit = locked;
+            self.last_report = self.env().block_number();
+            self.record_observation();
+            self.env().emit_event(ReportProfit { amount, locked });
+        }
+
+        /// Returns the spot exchange rate, assets per share scaled by `RATE_PRECISION`.
+        #[ink(message)]
+        pub fn exchange_rate(&self) -> u128 {
+            let total_supply = self.total_supply();
+            if total_supply == 0 {
+                return RATE_PRECISION;
+            }
+            let ua: U256 = self.unlocked_assets().into();
+            let rp: U256 = RATE_PRECISION.into();
+            let ts: U256 = total_supply.into();
+            (ua * rp / ts).as_u128()
+        }
+
+        /// Returns the time-weighted average exchange rate over the last `window` blocks,
+        /// or `None` if the window is empty or the observations do not reach back that far.
+        #[ink(message)]
+        pub fn rate_twap(&self, window: BlockNumber) -> Option<u128> {
+            self.twap_at(self.env().block_number(), window)
+        }
+
+        /// Returns the latest exchange rate observation.
+        #[ink(message)]
+        pub fn latest_observation(&self) -> Option<Observation> {
+            self.observations.get(&self.observation_index).copied()
+        }
+
+        fn record_observation(&mut self) {
+            let block = self.env().block_number();
+            let rate = self.exchange_rate();
+            self.push_observation(block, rate);
+        }
+
+        fn push_observation(&mut self, block: BlockNumber, rate: u128) {
+            let last = match self.observation_count {
+                0 => None,
+                _ => self.observations.get(&self.observation_index).copied(),
+            };
+            let observation = Observation {
+                block,
+                rate,
+                rate_cumulative: Self::rate_cumulative_at(last, block),
+            };
+            match last {
+                // later calls in the same block overwrite the observation
+                Some(last) if last.block == block => {}
+                None => self.observation_count = 1,
+                Some(_) => {
+                    self.observation_index = (self.observation_index + 1) % OBSERVATION_CARDINALITY;
+                    if self.observation_count < OBSERVATION_CARDINALITY {
+                        self.observation_count += 1;
+                    }
+                }
+            }
+            self.observations.insert(self.observation_index, observation);
+        }
+
+        fn rate_cumulative_at(observation: Option<Observation>, block: BlockNumber) -> u128 {
+            match observation {
+                Some(obs) => obs.rate_cumulative + obs.rate * (block - obs.block) as u128,
+                None => 0,
+            }
+        }
+
+        fn twap_at(&self, now: BlockNumber, window: BlockNumber) -> Option<u128> {
+            let count = self.observation_count;
+            if count == 0 || window == 0 || window > now {
+                return None;
+            }
+            let target = now - window;
+
+            // binary search for the last observation at or before `target`,
+            // positions run from the oldest observation to the latest
+            let oldest = (self.observation_index + OBSERVATION_CARDINALITY + 1 - count)
+                % OBSERVATION_CARDINALITY;
+            let slot = |pos: u32| (oldest + pos) % OBSERVATION_CARDINALITY;
+            let mut low = 0;
+            let mut high = count;
+            while low < high {
+                let mid = (low + high) / 2;
+                match self.observations.get(&slot(mid)) {
+                    Some(obs) if obs.block <= target => low = mid + 1,
+                    _ => high = mid,
+                }
+            }
+            if low == 0 {
+                return None;
+            }
+
+            let before = self.observations.get(&slot(low - 1)).copied();
+            let latest = self.observations.get(&self.observation_index).copied();
+            let cumulative =
+                Self::rate_cumulative_at(latest, now) - Self::rate_cumulative_at(before, target);
+            Some(cumulative / window as u128)
+        }
+
+        /// Same as `smart_balance`.
+        #[ink(message)]
+        pub fn total_assets(&self) -> Balance {
+            self.smart_balance()
+        }
+
+        /// Returns the capital held by the converter itself.
+        #[ink(message)]
+        pub fn idle_balance(&self) -> Balance {
+            self.token_balance_of(self.env().account_id())
+        }
+
+        fn calculate_mint_amount(&self, deposit_amount: Balance) -> Balance {
+            let initial_balance = self.unlocked_assets().saturating_sub(deposit_amount);
+            self.shares_for(deposit_amount, initial_balance)
+        }
+
+        // nDot minted for depositing `deposit_amount` into a pool holding `initial_balance`
+        fn shares_for(&self, deposit_amount: Balance, initial_balance: Balance) -> Balance {
+            let da: U256 = deposit_amount.into();
+            if self.total_supply() == 0 {
+                let decimal = 10u128.saturating_pow(self.token_decimals() as u32);
+                let dc: U256 = decimal.into();
+                let decimals =
+                    10u128.saturating_pow(self.underlying_decimals() as u32);
+                let td: U256 = decimals.into();
+                let value: U256 = da * dc / td;
+                return value.as_u128();
+            }
+            let total_supply = self.total_supply();
+            let ts: U256 = total_supply.into();
+            let ib: U256 = initial_balance.into();
+            let value = da * ts / ib;
+            return value.as_u128();
+        }
+
+        // underlying token redeemed by `shares` of nDot
+        fn assets_for(&self, shares: Balance) -> Balance {
+            let total_supply = self.total_supply();
+            if total_supply == 0 {
+                return 0;
+            }
+            let sh: U256 = shares.into();
+            let sb: U256 = self.unlocked_assets().into();
+            let ts: U256 = total_supply.into();
+            (sh * sb / ts).as_u128()
+        }
+
+        fn fee_of(amount: Balance, fee_bps: u128) -> Balance {
+            let am: U256 = amount.into();
+            let fee: U256 = fee_bps.into();
+            let bps: U256 = BPS_DENOMINATOR.into();
+            (am * fee / bps).as_u128()
+        }
+
+        /// Returns the nDot minted for converting `amount`, after the convert fee.
+        #[ink(message)]
+        pub fn preview_convert(&self, amount: Balance) -> Balance {
+            let fee = Self::fee_of(amount, self.convert_fee_bps);
+            self.shares_for(amount - fee, self.unlocked_assets())
+        }
+
+        /// Returns the underlying token paid for exiting `shares`, after the exit fee.
+        #[ink(message)]
+        pub fn preview_exit(&self, shares: Balance) -> Balance {
+            let fee = Self::fee_of(shares, self.exit_fee_bps);
+            self.assets_for(shares - fee)
+        }
+
+        /// Returns how much `account` can still convert under the deposit limits.
+        #[ink(message)]
+        pub fn max_deposit(&self, account: AccountId) -> Balance {
+            if self.allowlist_enabled && !self.is_allowlisted(account) {
+                return 0;
+            }
+            let initial_balance = self.smart_balance();
+            self.max_convert
+                .min(self.max_total_assets.saturating_sub(initial_balance))
+                .min(self.max_per_account.saturating_sub(self.net_deposit_of(account)))
+        }
+
+        /// Returns the underlying token converted for `account` net of its exits,
+        /// which `max_per_account` applies to.
+        #[ink(message)]
+        pub fn net_deposit_of(&self, account: AccountId) -> Balance {
+            self.net_deposits.get(&account).copied().unwrap_or(0)
+        }
+
+        fn check_deposit_limits(
+            &self,
+            account: AccountId,
+            amount: Balance,
+            initial_balance: Balance,
+        ) -> Result<()> {
+            if self.allowlist_enabled && !self.is_allowlisted(account) {
+                return Err(Error::NotAllowlisted);
+            }
+            if amount > self.max_convert {
+                return Err(Error::ExceedsMaxConvert);
+            }
+            if initial_balance.saturating_add(amount) > self.max_total_assets {
+                return Err(Error::ExceedsMaxTotalAssets);
+            }
+            if self.net_deposit_of(account).saturating_add(amount) > self.max_per_account {
+                return Err(Error::ExceedsMaxPerAccount);
+            }
+            Ok(())
+        }
+
+        /// Converts `amount` of the underlying token to nDot.
+        ///
+        /// # Errors
+        ///
+        /// Returns `NotAllowlisted` if the allowlist is enabled and the caller is not on it.
+        ///
+        /// Returns `ExceedsMaxConvert`, `ExceedsMaxTotalAssets` or `ExceedsMaxPerAccount`
+        /// if the convert is above one of the deposit limits.
+        #[ink(message)]
+        pub fn convert(&mut self, amount: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            self.convert_to(caller, caller, amount)
+        }
+
+        /// Converts `amount` of the caller's underlying token to nDot for `beneficiary`.
+        ///
+        /// The deposit limits and the flash loan lock apply to `beneficiary`.
+        ///
+        /// # Errors
+        ///
+        /// Same as `convert`, for `beneficiary`.
+        #[ink(message)]
+        pub fn convert_for(&mut self, beneficiary: AccountId, amount: Balance) -> Result<()> {
+            self.convert_to(beneficiary, beneficiary, amount)
+        }
+
+        /// Converts `amount` of the zapper's underlying token to nDot for the zapper,
+        /// on behalf of its user `account`.
+        ///
+        /// The deposit limits apply to `account`, the flash loan lock to the zapper.
+        ///
+        /// # Errors
+        ///
+        /// Same as `convert`, for `account`.
+        #[ink(message)]
+        pub fn convert_on_behalf(&mut self, account: AccountId, amount: Balance) -> Result<()> {
+            self.only_zapper();
+            let caller = self.env().caller();
+            self.convert_to(account, caller, amount)
+        }
+
+        // converts the caller's tokens to nDot for `beneficiary`, counted against the
+        // deposit limits of `account`
+        fn convert_to(
+            &mut self,
+            account: AccountId,
+            beneficiary: AccountId,
+            amount: Balance,
+        ) -> Result<()> {
+            assert!(!self.shutdown, "CapitalConverter: shut down");
+            assert!(amount > 0, "CapitalConverter: Cannot stake 0.");
+            assert_ne!(beneficiary, Default::default(), "beneficiary is zero");
+            let caller = self.env().caller();
+            let block_number = self.env().block_number();
+            let initial_balance = self.smart_balance();
+            self.check_deposit_limits(account, amount, initial_balance)?;
 
             assert!(
-                *self.deposit_at.get(&caller).unwrap() > 0,
-                "No deposit history"
+                self.token_transfer_from(caller, amount),
+                "transfer_from operation did not succeed"
             );
+
+            let fee = Self::fee_of(amount, self.convert_fee_bps);
+            if fee > 0 {
+                self.transfer_out(self.treasury, fee);
+                self.env().emit_event(FeeCharged {
+                    payer: caller,
+                    amount: fee,
+                    in_shares: false,
+                });
+            }
+
+            let value = self.calculate_mint_amount(amount - fee);
+            assert!(
+                self.mint(beneficiary, value).is_ok(),
+                "mint operation did not succeed"
+            );
+            self.lock_shares(beneficiary, value, block_number + self.lock_blocks);
+            let net_deposit = self.net_deposit_of(account);
+            self.net_deposits.insert(account, net_deposit + amount);
+
+            self.record_observation();
+
+            self.env().emit_event(Mint {
+                sender: Some(caller),
+                input: amount,
+                amount: value,
+            });
+            Ok(())
+        }
+
+        #[ink(message)]
+        pub  fn show_token(&self) ->AccountId {
+            self.token
+        }
+
+        /// Burns `amount` of the caller's nDot and sends the underlying token,
+        /// or the IOU for it, to the caller.
+        ///
+        /// # Errors
+        ///
+        /// Returns `InsufficientBalance` if the caller has less than `amount` nDot.
+        ///
+        /// Returns `SharesLocked` if part of `amount` is still locked, see `redeemable_at`.
+        ///
+        /// Returns `ExceedsFreeCapital` if the exit would take the pool below the minimum
+        /// capital requirement.
+        #[ink(message)]
+        pub fn exit(&mut self, amount: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            self.exit_from(caller, caller, amount)
+        }
+
+        /// Burns `amount` of the caller's nDot and sends the underlying token,
+        /// or the IOU for it, to `receiver`.
+        ///
+        /// # Errors
+        ///
+        /// Same as `exit`.
+        #[ink(message)]
+        pub fn exit_to(&mut self, receiver: AccountId, amount: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            self.exit_from(caller, receiver, amount)
+        }
+
+        /// Burns `amount` of the zapper's nDot and sends the underlying token, or the
+        /// IOU for it, to the zapper, on behalf of its user `account`.
+        ///
+        /// The exit counts against the net deposit of `account`.
+        ///
+        /// # Errors
+        ///
+        /// Same as `exit`.
+        #[ink(message)]
+        pub fn exit_on_behalf(&mut self, account: AccountId, amount: Balance) -> Result<()> {
+            self.only_zapper();
+            let caller = self.env().caller();
+            self.exit_from(account, caller, amount)
+        }
+
+        // burns the caller's nDot for `receiver`, counted against the net deposit of `account`
+        fn exit_from(
+            &mut self,
+            account: AccountId,
+            receiver: AccountId,
+            amount: Balance,
+        ) -> Result<()> {
+            assert!(!self.shutdown, "CapitalConverter: shut down, use emergency_exit");
+            assert_ne!(receiver, Default::default(), "receiver is zero");
+            let caller = self.env().caller();
+
+            let balance = self.balance_of(caller);
+            if amount == 0 || balance < amount {
+                return Err(Error::InsufficientBalance);
+            }
+            if amount > balance.saturating_sub(self.locked_balance(caller)) {
+                return Err(Error::SharesLocked);
+            }
+
+            let fee = Self::fee_of(amount, self.exit_fee_bps);
+            let value = self.assets_for(amount - fee);
+            if value > self.free_capital() {
+                return Err(Error::ExceedsFreeCapital);
+            }
+
+            if fee > 0 {
+                self.transfer_from_to(caller, self.treasury, fee)?;
+                self.env().emit_event(FeeCharged {
+                    payer: caller,
+                    amount: fee,
+                    in_shares: true,
+                });
+            }
+            let amount = amount - fee;
+            self.pay_or_issue_iou(receiver, value);
+            self.burn(caller, amount)?;
+            let net_deposit = self.net_deposit_of(account);
+            self.net_deposits.insert(account, net_deposit.saturating_sub(value));
+            self.record_observation();
+
+            self.env().emit_event(Burn {
+                sender: Some(caller),
+                amount,
+                output: value,
+            });
+            Ok(())
+        }
+
+        /// Returns the nDot of `account` still within the flash loan lock.
+        #[ink(message)]
+        pub fn locked_balance(&self, account: AccountId) -> Balance {
+            self.active_lock(account).amount
+        }
+
+        /// Returns the block from which all nDot of `account` can be redeemed.
+        #[ink(message)]
+        pub fn redeemable_at(&self, account: AccountId) -> BlockNumber {
+            let lock = self.active_lock(account);
+            if lock.amount == 0 {
+                return self.env().block_number();
+            }
+            lock.unlock_at
+        }
+
+        #[ink(message)]
+        pub fn set_lock_blocks(&mut self, blocks: BlockNumber) {
+            self.only_owner();
+            assert!(blocks <= MAX_LOCK_BLOCKS, "lock too long");
+            self.lock_blocks = blocks;
+            self.env().emit_event(SetLockBlocks { blocks });
+        }
+
+        #[ink(message)]
+        pub fn lock_blocks(&self) -> BlockNumber {
+            self.lock_blocks
+        }
+
+        fn active_lock(&self, account: AccountId) -> ShareLock {
+            match self.locks.get(&account) {
+                Some(lock) if lock.unlock_at > self.env().block_number() => *lock,
+                _ => ShareLock {
+                    amount: 0,
+                    unlock_at: 0,
+                },
+            }
+        }
+
+        // adds `amount` to the lock of `account`, which then unlocks at the later block
+        fn lock_shares(&mut self, account: AccountId, amount: Balance, unlock_at: BlockNumber) {
+            if amount == 0 || unlock_at <= self.env().block_number() {
+                return;
+            }
+            let lock = self.active_lock(account);
+            self.locks.insert(
+                account,
+                ShareLock {
+                    amount: lock.amount + amount,
+                    unlock_at: lock.unlock_at.max(unlock_at),
+                },
+            );
+        }
+
+        /// Pays `amount` of pooled capital to `to` for the claim `claim_id`.
+        ///
+        /// Payouts of at least `large_payout` are only proposed here and are paid
+        /// once an approver calls `approve_payout`.
+        #[ink(message)]
+        pub fn payouts(&mut self, claim_id: u64, to: AccountId, amount: Balance) {
+            self.only_operator();
+
+            assert!(to != Default::default(), "to is zero");
+            assert!(amount > 0, "invalid amount");
+            assert!(
+                !self.payout_records.contains_key(&claim_id),
+                "claim already paid"
+            );
+            assert!(
+                !self.pending_payouts.contains_key(&claim_id),
+                "claim already pending"
+            );
+
+            let record = PayoutRecord {
+                to,
+                amount,
+                operator: self.env().caller(),
+                approver: Default::default(),
+                block: self.env().block_number(),
+            };
+            if amount >= self.large_payout {
+                self.pending_payouts.insert(claim_id, record);
+                self.env().emit_event(PayoutProposed {
+                    claim_id,
+                    to,
+                    amount,
+                });
+                return;
+            }
+
+            self.execute_payout(claim_id, record);
+        }
+
+        /// Approves and pays a pending large payout.
+        #[ink(message)]
+        pub fn approve_payout(&mut self, claim_id: u64) {
+            let caller = self.env().caller();
+            assert!(self.is_approver(caller), "not approver");
+            let mut record = self
+                .pending_payouts
+                .take(&claim_id)
+                .expect("no pending payout");
+            assert!(record.operator != caller, "approver is the operator");
+
+            record.approver = caller;
+            self.env().emit_event(PayoutApproved {
+                claim_id,
+                approver: caller,
+            });
+            self.execute_payout(claim_id, record);
+        }
+
+        #[ink(message)]
+        pub fn cancel_payout(&mut self, claim_id: u64) {
+            self.only_operator();
             assert!(
-                *self.deposit_at.get(&caller).unwrap() < self.env().block_number(),
-                "Reject flashloan"
+                self.pending_payouts.take(&claim_id).is_some(),
+                "no pending payout"
+            );
+            self.env().emit_event(PayoutCancelled { claim_id });
+        }
+
+        /// Returns the payout made for `claim_id`, if any.
+        #[ink(message)]
+        pub fn payout_history(&self, claim_id: u64) -> Option<PayoutRecord> {
+            self.payout_records.get(&claim_id).copied()
+        }
+
+        /// Returns the large payout for `claim_id` waiting for approval, if any.
+        #[ink(message)]
+        pub fn pending_payout(&self, claim_id: u64) -> Option<PayoutRecord> {
+            self.pending_payouts.get(&claim_id).copied()
+        }
+
+        #[ink(message)]
+        pub fn current_epoch(&self) -> u32 {
+            self.env().block_number() / self.epoch_length
+        }
+
+        /// Returns the total paid out in `epoch`.
+        #[ink(message)]
+        pub fn epoch_payouts(&self, epoch: u32) -> Balance {
+            self.epoch_payouts.get(&epoch).copied().unwrap_or(0)
+        }
+
+        /// Returns the value nDot holders lost to the payouts of `epoch`, the supply
+        /// at each payout valued at the exchange rates before and after it.
+        #[ink(message)]
+        pub fn epoch_loss(&self, epoch: u32) -> Balance {
+            self.epoch_losses.get(&epoch).copied().unwrap_or(0)
+        }
+
+        /// Returns the loss of the pool to payouts since deployment.
+        #[ink(message)]
+        pub fn total_loss(&self) -> Balance {
+            self.total_loss
+        }
+
+        #[ink(message)]
+        pub fn loss_count(&self) -> u64 {
+            self.loss_count
+        }
+
+        /// Returns up to `limit` losses from the `start`th one on, oldest first.
+        #[ink(message)]
+        pub fn losses(&self, start: u64, limit: u64) -> Vec<Loss> {
+            let end = start.saturating_add(limit).min(self.loss_count);
+            (start..end)
+                .filter_map(|index| self.losses.get(&index).copied())
+                .collect()
+        }
+
+        fn record_loss(&mut self, claim_id: u64, assets_paid: Balance, rate_before: u128) {
+            let rate_after = self.exchange_rate();
+            let loss = Loss {
+                claim_id,
+                assets_paid,
+                rate_before,
+                rate_after,
+                block: self.env().block_number(),
+            };
+            self.losses.insert(self.loss_count, loss);
+            self.loss_count += 1;
+            self.total_loss += assets_paid;
+
+            let ts: U256 = self.total_supply().into();
+            let drop: U256 = rate_before.saturating_sub(rate_after).into();
+            let rp: U256 = RATE_PRECISION.into();
+            let epoch = self.current_epoch();
+            let epoch_loss = self.epoch_loss(epoch);
+            self.epoch_losses
+                .insert(epoch, epoch_loss + (ts * drop / rp).as_u128());
+            self.env().emit_event(LossRecorded {
+                claim_id,
+                assets_paid,
+                rate_before,
+                rate_after,
+            });
+        }
+
+        #[ink(message)]
+        pub fn is_approver(&self, account: AccountId) -> bool {
+            self.approvers.get(&account).copied().unwrap_or(false)
+        }
+
+        #[ink(message)]
+        pub fn set_approver(&mut self, approver: AccountId, enabled: bool) {
+            self.only_owner();
+            assert!(approver != Default::default(), "approver is zero");
+            self.approvers.insert(approver, enabled);
+            self.env().emit_event(SetApprover { approver, enabled });
+        }
+
+        /// Sets the max payouts per epoch, in basis points of the capital.
+        #[ink(message)]
+        pub fn set_payout_cap(&mut self, cap: u128) {
+            self.only_owner();
+            assert!(cap <= BPS_DENOMINATOR, "invalid cap");
+            self.payout_cap = cap;
+            self.env().emit_event(SetPayoutCap { cap });
+        }
+
+        #[ink(message)]
+        pub fn set_large_payout(&mut self, threshold: Balance) {
+            self.only_owner();
+            self.large_payout = threshold;
+            self.env().emit_event(SetLargePayout { threshold });
+        }
+
+        #[ink(message)]
+        pub fn set_epoch_length(&mut self, blocks: BlockNumber) {
+            self.only_owner();
+            assert!(blocks > 0, "invalid epoch length");
+            self.epoch_length = blocks;
+            self.env().emit_event(SetEpochLength { blocks });
+        }
+
+        fn execute_payout(&mut self, claim_id: u64, mut record: PayoutRecord) {
+            assert!(!self.shutdown, "CapitalConverter: shut down");
+            let to = record.to;
+            let amount = record.amount;
+            assert!(
+                amount <= self.free_capital(),
+                "exceeding the minimum capital requirement"
+            );
+
+            // the cap is measured against the capital before this epoch's payouts
+            let epoch = self.current_epoch();
+            let paid = self.epoch_payouts(epoch);
+            let capital: U256 = (self.smart_balance() + paid).into();
+            let cap: U256 = self.payout_cap.into();
+            let bps: U256 = BPS_DENOMINATOR.into();
+            assert!(
+                paid + amount <= (capital * cap / bps).as_u128(),
+                "exceeding the epoch payout cap"
+            );
+
+            let rate_before = self.exchange_rate();
+            // the liquid capital owed to the queued IOUs is not available for payouts
+            let idle = self.ensure_liquidity(self.iou_total + amount);
+            assert!(
+                idle.saturating_sub(self.iou_total) >= amount,
+                "insufficient liquidity"
+            );
+            self.transfer_out(to, amount);
+
+            record.block = self.env().block_number();
+            self.payout_records.insert(claim_id, record);
+            self.epoch_payouts.insert(epoch, paid + amount);
+            self.record_loss(claim_id, amount, rate_before);
+            self.record_observation();
+
+            self.env().emit_event(Payouts {
+                claim_id,
+                to: Some(to),
+                amount,
+            });
+        }
+
+        /// Shuts the pool down for good, blocking `convert`, `exit` and `payouts`
+        /// and freezing the exchange rate for `emergency_exit`.
+        #[ink(message)]
+        pub fn shutdown(&mut self) {
+            let assets = self.smart_balance();
+            self.freeze(assets);
+        }
+
+        // shuts the pool down with the exchange rate frozen at `assets` over the supply
+        fn freeze(&mut self, assets: Balance) {
+            let caller = self.env().caller();
+            assert!(
+                caller == self.guardian || caller == self.owner,
+                "not guardian"
+            );
+            assert!(!self.shutdown, "already shut down");
+            self.shutdown = true;
+            self.shutdown_assets = assets.saturating_sub(self.iou_total);
+            self.shutdown_supply = self.total_supply();
+            self.env().emit_event(Shutdown {
+                guardian: caller,
+                assets: self.shutdown_assets,
+                supply: self.shutdown_supply,
+            });
+        }
+
+        /// Burns `amount` of the caller's nDot for its pro rata share at the rate
+        /// frozen by `shutdown`.
+        #[ink(message)]
+        pub fn emergency_exit(&mut self, amount: Balance) {
+            let caller = self.env().caller();
+            let value = self.burn_at_shutdown_rate(caller, amount);
+            self.pay_or_issue_iou(caller, value);
+
+            self.env().emit_event(EmergencyExit {
+                sender: caller,
+                amount,
+                output: value,
+            });
+        }
+
+        // burns `amount` nDot of `account` and returns what they are worth at the frozen rate
+        fn burn_at_shutdown_rate(&mut self, account: AccountId, amount: Balance) -> Balance {
+            assert!(self.shutdown, "not shut down");
+            assert!(
+                self.balance_of(account) >= amount && amount > 0,
+                "CapitalConverter: insufficient assets"
+            );
+
+            let am: U256 = amount.into();
+            let sa: U256 = self.shutdown_assets.into();
+            let ss: U256 = self.shutdown_supply.into();
+            let value = (am * sa / ss).as_u128();
+
+            assert!(
+                self.burn(account, amount).is_ok(),
+                "burn operation did not succeed"
+            );
+            // the burn ignores the flash loan lock, which keeps at most what is left
+            let lock = self.active_lock(account);
+            let balance = self.balance_of(account);
+            if lock.amount > balance {
+                self.locks.insert(
+                    account,
+                    ShareLock {
+                        amount: balance,
+                        unlock_at: lock.unlock_at,
+                    },
+                );
+            }
+            value
+        }
+
+        #[ink(message)]
+        pub fn is_shutdown(&self) -> bool {
+            self.shutdown
+        }
+
+        /// Returns the exchange rate frozen at shutdown, as assets over supply.
+        #[ink(message)]
+        pub fn shutdown_rate(&self) -> (Balance, Balance) {
+            (self.shutdown_assets, self.shutdown_supply)
+        }
+
+        /// Records why the pool was shut down.
+        #[ink(message)]
+        pub fn set_post_mortem(&mut self, reason: String) {
+            self.only_owner();
+            assert!(self.shutdown, "not shut down");
+            self.post_mortem = Some(reason);
+        }
+
+        #[ink(message)]
+        pub fn post_mortem(&self) -> Option<String> {
+            self.post_mortem.clone()
+        }
+
+        #[ink(message)]
+        pub fn set_guardian(&mut self, guardian: AccountId) {
+            self.only_owner();
+            assert!(guardian != Default::default(), "guardian is zero");
+            self.guardian = guardian;
+            self.env().emit_event(SetGuardian { guardian });
+        }
+
+        #[ink(message)]
+        pub fn add_strategy(&mut self, strategy: AccountId, debt_ceiling: Balance) {
+            self.only_owner();
+            assert!(strategy != Default::default(), "strategy is zero");
+            assert!(
+                !self.strategy_params.contains_key(&strategy),
+                "Duplicate Strategy!"
+            );
+            self.strategies.push(strategy);
+            self.strategy_params.insert(
+                strategy,
+                StrategyParams {
+                    debt_ceiling,
+                    debt: 0,
+                },
+            );
+            self.env().emit_event(AddStrategy {
+                strategy,
+                debt_ceiling,
+            });
+        }
+
+        #[ink(message)]
+        pub fn set_debt_ceiling(&mut self, strategy: AccountId, debt_ceiling: Balance) {
+            self.only_owner();
+            let params = self
+                .strategy_params
+                .get_mut(&strategy)
+                .expect("unknown strategy");
+            params.debt_ceiling = debt_ceiling;
+            self.env().emit_event(SetDebtCeiling {
+                strategy,
+                debt_ceiling,
+            });
+        }
+
+        /// Sets the max share of the capital allocated to strategies, in basis points.
+        #[ink(message)]
+        pub fn set_max_allocation(&mut self, max: u128) {
+            self.only_owner();
+            assert!(max <= BPS_DENOMINATOR, "invalid allocation");
+            self.max_allocation = max;
+            self.env().emit_event(SetMaxAllocation { max });
+        }
+
+        /// Moves `amount` of idle capital to `strategy`.
+        #[ink(message)]
+        pub fn allocate(&mut self, strategy: AccountId, amount: Balance) {
+            self.only_operator();
+            assert!(!self.shutdown, "CapitalConverter: shut down");
+            let mut params = *self
+                .strategy_params
+                .get(&strategy)
+                .expect("unknown strategy");
+            assert!(
+                params.debt + amount <= params.debt_ceiling,
+                "exceeding the debt ceiling"
+            );
+            let capital: U256 = self.smart_balance().into();
+            let max: U256 = self.max_allocation.into();
+            let bps: U256 = BPS_DENOMINATOR.into();
+            assert!(
+                self.total_debt + amount <= (capital * max / bps).as_u128(),
+                "exceeding the max allocation"
+            );
+
+            self.transfer_out(strategy, amount);
+            self.strategy_deposit(strategy, amount);
+
+            params.debt += amount;
+            self.strategy_params.insert(strategy, params);
+            self.total_debt += amount;
+            self.env().emit_event(Allocate { strategy, amount });
+        }
+
+        /// Moves up to `amount` of capital back from `strategy`.
+        #[ink(message)]
+        pub fn deallocate(&mut self, strategy: AccountId, amount: Balance) {
+            self.only_operator();
+            assert!(
+                self.strategy_params.contains_key(&strategy),
+                "unknown strategy"
+            );
+            self.withdraw_from_strategy(strategy, amount);
+        }
+
+        /// Collects the yield of `strategy` into the converter.
+        #[ink(message)]
+        pub fn harvest(&mut self, strategy: AccountId) -> Balance {
+            self.only_operator();
+            assert!(
+                self.strategy_params.contains_key(&strategy),
+                "unknown strategy"
+            );
+            let profit = self.strategy_harvest(strategy);
+            self.lock_profit(profit);
+            self.env().emit_event(Harvest { strategy, profit });
+            profit
+        }
+
+        #[ink(message)]
+        pub fn strategies(&self) -> Vec<AccountId> {
+            self.strategies.clone()
+        }
+
+        #[ink(message)]
+        pub fn strategy_params(&self, strategy: AccountId) -> Option<StrategyParams> {
+            self.strategy_params.get(&strategy).copied()
+        }
+
+        #[ink(message)]
+        pub fn total_debt(&self) -> Balance {
+            self.total_debt
+        }
+
+        fn withdraw_from_strategy(&mut self, strategy: AccountId, amount: Balance) -> Balance {
+            let withdrawn = self.strategy_withdraw(strategy, amount);
+
+            let mut params = *self.strategy_params.get(&strategy).unwrap();
+            let repaid = withdrawn.min(params.debt);
+            params.debt -= repaid;
+            self.strategy_params.insert(strategy, params);
+            self.total_debt -= repaid;
+            self.env().emit_event(Deallocate {
+                strategy,
+                amount: withdrawn,
+            });
+            withdrawn
+        }
+
+        // pulls capital back from the strategies, in order, until `amount` is held idle.
+        // returns the idle capital reached, less than `amount` if the strategies are short.
+        fn ensure_liquidity(&mut self, amount: Balance) -> Balance {
+            let mut idle = self.idle_balance();
+            let mut i = 0;
+            while idle < amount && i < self.strategies.len() {
+                let strategy = self.strategies[i];
+                idle += self.withdraw_from_strategy(strategy, amount - idle);
+                i += 1;
+            }
+            self.idle_balance()
+        }
+
+        /// Settles up to `max_count` IOUs, oldest first, with the liquid capital.
+        #[ink(message)]
+        pub fn settle_ious(&mut self, max_count: u32) {
+            let mut available = self.idle_balance();
+            let mut count = 0;
+            while count < max_count && self.iou_head < self.iou_tail && available > 0 {
+                let id = self.iou_head;
+                let mut iou = *self.ious.get(&id).unwrap();
+                let paid = iou.amount.min(available);
+                self.transfer_out(iou.owner, paid);
+                available -= paid;
+                iou.amount -= paid;
+                self.iou_total -= paid;
+                let balance = self.iou_balance(iou.owner);
+                self.iou_balances.insert(iou.owner, balance - paid);
+                self.env().emit_event(IouSettled {
+                    id,
+                    owner: iou.owner,
+                    amount: paid,
+                });
+
+                if iou.amount > 0 {
+                    self.ious.insert(id, iou);
+                    break;
+                }
+                self.ious.take(&id);
+                self.iou_head += 1;
+                count += 1;
+            }
+        }
+
+        /// Transfers the IOU `id` of the caller to `to`.
+        #[ink(message)]
+        pub fn transfer_iou(&mut self, id: u64, to: AccountId) {
+            assert!(to != Default::default(), "to is zero");
+            let caller = self.env().caller();
+            let mut iou = *self.ious.get(&id).expect("no iou");
+            assert!(iou.owner == caller, "not iou owner");
+
+            let from_balance = self.iou_balance(caller);
+            self.iou_balances.insert(caller, from_balance - iou.amount);
+            let to_balance = self.iou_balance(to);
+            self.iou_balances.insert(to, to_balance + iou.amount);
+            iou.owner = to;
+            self.ious.insert(id, iou);
+            self.env().emit_event(IouTransfer {
+                id,
+                from: caller,
+                to,
+            });
+        }
+
+        #[ink(message)]
+        pub fn iou(&self, id: u64) -> Option<Iou> {
+            self.ious.get(&id).copied()
+        }
+
+        /// Returns the outstanding IOUs of `account`.
+        #[ink(message)]
+        pub fn iou_balance(&self, account: AccountId) -> Balance {
+            self.iou_balances.get(&account).copied().unwrap_or(0)
+        }
+
+        #[ink(message)]
+        pub fn iou_total(&self) -> Balance {
+            self.iou_total
+        }
+
+        /// Returns the id of the next IOU to settle and the id the next IOU will get.
+        #[ink(message)]
+        pub fn iou_queue(&self) -> (u64, u64) {
+            (self.iou_head, self.iou_tail)
+        }
+
+        // pays what is liquid after the queued IOUs, the rest of `value` becomes an IOU
+        fn pay_or_issue_iou(&mut self, to: AccountId, value: Balance) {
+            // the strategies may be short, what they can't cover is queued as an IOU
+            let idle = self.ensure_liquidity(self.iou_total + value);
+            let paid = value.min(idle.saturating_sub(self.iou_total));
+            if paid > 0 {
+                self.transfer_out(to, paid);
+            }
+            if paid < value {
+                self.issue_iou(to, value - paid);
+            }
+        }
+
+        fn issue_iou(&mut self, owner: AccountId, amount: Balance) {
+            let id = self.iou_tail;
+            self.ious.insert(id, Iou { owner, amount });
+            self.iou_tail += 1;
+            self.iou_total += amount;
+            let balance = self.iou_balance(owner);
+            self.iou_balances.insert(owner, balance + amount);
+            self.env().emit_event(IouIssued { id, owner, amount });
+        }
+
+        #[ink(message)]
+        pub fn set_operator(&mut self, operator: AccountId) {
+            self.only_owner();
+            assert!(operator != Default::default(), "operator is zero");
+            self.operator = operator;
+            self.env().emit_event(SetOperator { operator });
+        }
+
+        /// Sets the zap router allowed to convert and exit on behalf of its users.
+        #[ink(message)]
+        pub fn set_zapper(&mut self, zapper: AccountId) {
+            self.only_owner();
+            assert!(zapper != Default::default(), "zapper is zero");
+            self.zapper = zapper;
+            self.env().emit_event(SetZapper { zapper });
+        }
+
+        #[ink(message)]
+        pub fn set_max_convert(&mut self, max: Balance) {
+            self.only_owner();
+            self.max_convert = max;
+            self.env().emit_event(SetMaxConvert { max });
+        }
+
+        /// Sets the convert and exit fees in basis points, at most `MAX_FEE_BPS` each.
+        #[ink(message)]
+        pub fn set_fees(&mut self, convert_fee: u128, exit_fee: u128) {
+            self.only_owner();
+            assert!(
+                convert_fee <= MAX_FEE_BPS && exit_fee <= MAX_FEE_BPS,
+                "fee too high"
+            );
+            if convert_fee > 0 || exit_fee > 0 {
+                assert!(self.treasury != Default::default(), "treasury is zero");
+            }
+            self.convert_fee_bps = convert_fee;
+            self.exit_fee_bps = exit_fee;
+            self.env().emit_event(SetFees {
+                convert_fee,
+                exit_fee,
+            });
+        }
+
+        #[ink(message)]
+        pub fn set_treasury(&mut self, treasury: AccountId) {
+            self.only_owner();
+            assert!(treasury != Default::default(), "treasury is zero");
+            self.treasury = treasury;
+            self.env().emit_event(SetTreasury { treasury });
+        }
+
+        #[ink(message)]
+        pub fn convert_fee_bps(&self) -> u128 {
+            self.convert_fee_bps
+        }
+
+        #[ink(message)]
+        pub fn exit_fee_bps(&self) -> u128 {
+            self.exit_fee_bps
+        }
+
+        #[ink(message)]
+        pub fn treasury(&self) -> AccountId {
+            self.treasury
+        }
+
+        /// Sets the max underlying token held by the pool and by one account.
+        #[ink(message)]
+        pub fn set_deposit_limits(&mut self, max_total_assets: Balance, max_per_account: Balance) {
+            self.only_owner();
+            self.max_total_assets = max_total_assets;
+            self.max_per_account = max_per_account;
+            self.env().emit_event(SetDepositLimits {
+                max_total_assets,
+                max_per_account,
+            });
+        }
+
+        #[ink(message)]
+        pub fn max_total_assets(&self) -> Balance {
+            self.max_total_assets
+        }
+
+        #[ink(message)]
+        pub fn max_per_account(&self) -> Balance {
+            self.max_per_account
+        }
+
+        /// Switches the allowlist for guarded launch phases.
+        #[ink(message)]
+        pub fn set_allowlist_enabled(&mut self, enabled: bool) {
+            self.only_owner();
+            self.allowlist_enabled = enabled;
+            self.env().emit_event(SetAllowlistEnabled { enabled });
+        }
+
+        #[ink(message)]
+        pub fn set_allowlisted(&mut self, account: AccountId, allowed: bool) {
+            self.only_owner();
+            self.allowlist.insert(account, allowed);
+            self.env().emit_event(SetAllowlisted { account, allowed });
+        }
+
+        #[ink(message)]
+        pub fn allowlist_enabled(&self) -> bool {
+            self.allowlist_enabled
+        }
+
+        #[ink(message)]
+        pub fn is_allowlisted(&self, account: AccountId) -> bool {
+            self.allowlist.get(&account).copied().unwrap_or(false)
+        }
+
+        #[ink(message)]
+        pub fn set_cover(&mut self, cover: AccountId) {
+            self.only_owner();
+            assert!(cover != Default::default(), "cover is zero");
+            self.cover = cover;
+            self.env().emit_event(SetCover { cover });
+        }
+
+        /// Updates the capital locked by active policies, called by the cover contract
+        /// whenever cover is sold or expires.
+        #[ink(message)]
+        pub fn set_locked_capital(&mut self, amount: Balance) {
+            self.only_cover();
+            self.locked_capital = amount;
+            self.env().emit_event(SetLockedCapital { amount });
+        }
+
+        /// Sets the minimum capital requirement ratio in basis points of the locked capital.
+        #[ink(message)]
+        pub fn set_mcr_ratio(&mut self, ratio: u128) {
+            self.only_owner();
+            self.mcr_ratio = ratio;
+            self.env().emit_event(SetMcrRatio { ratio });
+        }
+
+        #[ink(message)]
+        pub fn locked_capital(&self) -> Balance {
+            self.locked_capital
+        }
+
+        #[ink(message)]
+        pub fn mcr_ratio(&self) -> u128 {
+            self.mcr_ratio
+        }
+
+        /// Capital the pool must keep to back active risk.
+        #[ink(message)]
+        pub fn minimum_capital(&self) -> Balance {
+            let locked: U256 = self.locked_capital.into();
+            let ratio: U256 = self.mcr_ratio.into();
+            let bps: U256 = BPS_DENOMINATOR.into();
+            (locked * ratio / bps).as_u128()
+        }
+
+        /// Capital above the outstanding IOUs and the minimum capital requirement,
+        /// available to `exit` and `payouts`.
+        #[ink(message)]
+        pub fn free_capital(&self) -> Balance {
+            self.smart_balance()
+                .saturating_sub(self.iou_total)
+                .saturating_sub(self.minimum_capital())
+        }
+
+        /// Contract owner.
+        #[ink(message)]
+        pub fn owner(&self) -> Option<AccountId> {
+            Some(self.owner)
+        }
+
+        /// transfer contract ownership to new owner.
+        #[ink(message)]
+        pub fn transfer_ownership(&mut self, new_owner: Option<AccountId>) {
+            self.only_owner();
+            if let Some(owner) = new_owner {
+                self.owner = owner;
+            }
+        }
+
+        // sends `amount` of the underlying token out of the pool
+        fn transfer_out(&mut self, to: AccountId, amount: Balance) {
+            assert!(
+                self.token_transfer(to, amount),
+                "transfer operation did not succeed"
+            );
+        }
+
+        // the calls into the underlying token and the strategies go through the helpers
+        // below, the unit tests swap them for the `mock` module as they can't reach
+        // other contracts off-chain.
+        #[cfg(not(test))]
+        fn token_balance_of(&self, owner: AccountId) -> Balance {
+            self.token_contract.balance_of(owner)
+        }
+
+        #[cfg(not(test))]
+        fn underlying_decimals(&self) -> u8 {
+            self.token_contract.token_decimals().unwrap()
+        }
+
+        #[cfg(not(test))]
+        fn token_transfer(&mut self, to: AccountId, amount: Balance) -> bool {
+            self.token_contract.transfer(to, amount).is_ok()
+        }
+
+        #[cfg(not(test))]
+        fn token_transfer_from(&mut self, from: AccountId, amount: Balance) -> bool {
+            self.token_contract
+                .transfer_from(from, self.env().account_id(), amount)
+                .is_ok()
+        }
+
+        #[cfg(not(test))]
+        fn strategy_total_assets(&self, strategy: AccountId) -> Balance {
+            StrategyRef::new(strategy).total_assets()
+        }
+
+        #[cfg(not(test))]
+        fn strategy_deposit(&mut self, strategy: AccountId, amount: Balance) {
+            StrategyRef::new(strategy).deposit(amount)
+        }
+
+        #[cfg(not(test))]
+        fn strategy_withdraw(&mut self, strategy: AccountId, amount: Balance) -> Balance {
+            StrategyRef::new(strategy).withdraw(amount)
+        }
+
+        #[cfg(not(test))]
+        fn strategy_harvest(&mut self, strategy: AccountId) -> Balance {
+            StrategyRef::new(strategy).harvest()
+        }
+
+        #[cfg(test)]
+        fn token_balance_of(&self, owner: AccountId) -> Balance {
+            mock::balance_of(owner)
+        }
+
+        #[cfg(test)]
+        fn underlying_decimals(&self) -> u8 {
+            mock::DECIMALS
+        }
+
+        #[cfg(test)]
+        fn token_transfer(&mut self, to: AccountId, amount: Balance) -> bool {
+            mock::transfer(self.env().account_id(), to, amount)
+        }
+
+        #[cfg(test)]
+        fn token_transfer_from(&mut self, from: AccountId, amount: Balance) -> bool {
+            mock::transfer(from, self.env().account_id(), amount)
+        }
+
+        #[cfg(test)]
+        fn strategy_total_assets(&self, strategy: AccountId) -> Balance {
+            mock::balance_of(strategy)
+        }
+
+        #[cfg(test)]
+        fn strategy_deposit(&mut self, strategy: AccountId, amount: Balance) {
+            mock::strategy_deposit(strategy, amount)
+        }
+
+        #[cfg(test)]
+        fn strategy_withdraw(&mut self, strategy: AccountId, amount: Balance) -> Balance {
+            mock::strategy_withdraw(strategy, self.env().account_id(), amount)
+        }
+
+        #[cfg(test)]
+        fn strategy_harvest(&mut self, strategy: AccountId) -> Balance {
+            mock::strategy_harvest(strategy, self.env().account_id())
+        }
+
+        fn only_owner(&self) {
+            assert_eq!(self.env().caller(), self.owner);
+        }
+
+        fn only_operator(&self) {
+            assert!(self.env().caller() == self.operator, "not operator");
+        }
+
+        fn only_cover(&self) {
+            assert!(self.env().caller() == self.cover, "not cover");
+        }
+
+        fn only_zapper(&self) {
+            assert!(self.env().caller() == self.zapper, "not zapper");
+        }
+    }
+
+    /// Stands in for the underlying token and the strategies in the unit tests. The
+    /// strategies hold their capital as token balance and report their principal
+    /// like `MockStrategy`.
+    #[cfg(test)]
+    mod mock {
+        use super::{AccountId, Balance};
+        use std::cell::RefCell;
+        use std::collections::BTreeMap;
+
+        pub const DECIMALS: u8 = 8;
+
+        thread_local! {
+            static BALANCES: RefCell<BTreeMap<AccountId, Balance>> = RefCell::new(BTreeMap::new());
+            static PRINCIPALS: RefCell<BTreeMap<AccountId, Balance>> = RefCell::new(BTreeMap::new());
+        }
+
+        pub fn balance_of(owner: AccountId) -> Balance {
+            BALANCES.with(|b| b.borrow().get(&owner).copied().unwrap_or(0))
+        }
+
+        pub fn set_balance(owner: AccountId, amount: Balance) {
+            BALANCES.with(|b| b.borrow_mut().insert(owner, amount));
+        }
+
+        pub fn transfer(from: AccountId, to: AccountId, amount: Balance) -> bool {
+            let from_balance = balance_of(from);
+            if from_balance < amount {
+                return false;
+            }
+            set_balance(from, from_balance - amount);
+            set_balance(to, balance_of(to) + amount);
+            true
+        }
+
+        pub fn principal_of(strategy: AccountId) -> Balance {
+            PRINCIPALS.with(|p| p.borrow().get(&strategy).copied().unwrap_or(0))
+        }
+
+        pub fn strategy_deposit(strategy: AccountId, amount: Balance) {
+            let principal = principal_of(strategy);
+            PRINCIPALS.with(|p| p.borrow_mut().insert(strategy, principal + amount));
+        }
+
+        pub fn strategy_withdraw(strategy: AccountId, vault: AccountId, amount: Balance) -> Balance {
+            let amount = amount.min(balance_of(strategy));
+            let principal = principal_of(strategy).saturating_sub(amount);
+            PRINCIPALS.with(|p| p.borrow_mut().insert(strategy, principal));
+            transfer(strategy, vault, amount);
+            amount
+        }
+
+        pub fn strategy_harvest(strategy: AccountId, vault: AccountId) -> Balance {
+            let profit = balance_of(strategy).saturating_sub(principal_of(strategy));
+            transfer(strategy, vault, profit);
+            profit
+        }
+    }
+
+    /// Unit tests in Rust are normally defined within such a `#[cfg(test)]`
+  /// module and test functions are marked with a `#[test]` attribute.
+  /// The below code is technically just normal Rust code.
+    #[cfg(test)]
+    mod tests {
+        /// Imports all the definitions from the outer scope so we can use them here.
+        use super::*;
+
+        /// Imports `ink_lang` so we can use `#[ink::test]`.
+        use ink_lang as ink;
+        use ink_env::AccountId;
+
+        #[ink::test]
+        fn mint_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            assert_eq!(capital_converter.mint(accounts.alice, 1000), Ok(()));
+
+            assert_eq!(*capital_converter.total_supply, 1000);
+
+            assert_eq!(capital_converter.balance_of(accounts.alice), 1000);
+        }
+
+        #[ink::test]
+        fn burn_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            assert_eq!(capital_converter.mint(accounts.alice, 1000), Ok(()));
+
+            assert_eq!(capital_converter.burn(accounts.alice, 200), Ok(()));
+
+            assert_eq!(capital_converter.balance_of(accounts.alice), 800);
+
+            assert_eq!(*capital_converter.total_supply, 800);
+        }
+
+        #[ink::test]
+        fn transfer_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            assert_eq!(capital_converter.mint(accounts.alice, 1000), Ok(()));
+
+            assert_eq!(capital_converter.balance_of(accounts.bob), 0);
+
+            assert_eq!(capital_converter.transfer(accounts.bob, 100), Ok(()));
+
+            assert_eq!(capital_converter.balance_of(accounts.bob), 100);
+
+            assert_eq!(capital_converter.balance_of(accounts.alice), 900);
+        }
+
+        #[ink::test]
+        fn allowance_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            assert_eq!(capital_converter.mint(accounts.alice, 1000), Ok(()));
+
+            assert_eq!(capital_converter.approve(accounts.bob, 100), Ok(()));
+
+            assert_eq!(capital_converter.allowance(accounts.alice, accounts.bob), 100);
+        }
+
+        #[ink::test]
+        fn time_weighted_balance_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            assert_eq!(capital_converter.mint(accounts.alice, 1000), Ok(()));
+            for _ in 0..10 {
+                ink_env::test::advance_block::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot advance block");
+            }
+            assert_eq!(capital_converter.transfer(accounts.bob, 500), Ok(()));
+            for _ in 0..10 {
+                ink_env::test::advance_block::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot advance block");
+            }
+
+            assert_eq!(capital_converter.time_weighted_balance(accounts.alice, 0, 20), 750);
+            assert_eq!(capital_converter.time_weighted_balance(accounts.bob, 0, 20), 250);
+            assert_eq!(capital_converter.time_weighted_balance(accounts.bob, 10, 20), 500);
+            assert_eq!(capital_converter.time_weighted_total_supply(0, 20), 1000);
+        }
+
+        #[ink::test]
+        fn shutdown_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+            let contract = ink_env::account_id::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get contract id");
+
+            mock::set_balance(accounts.alice, 1000);
+            assert_eq!(capital_converter.convert(1000), Ok(()));
+            // the pool doubled its capital
+            mock::set_balance(contract, 2000);
+            capital_converter.set_guardian(accounts.bob);
+
+            capital_converter.shutdown();
+            assert_eq!(capital_converter.is_shutdown(), true);
+            assert_eq!(capital_converter.shutdown_rate(), (2000, 1000));
+
+            capital_converter.emergency_exit(300);
+            assert_eq!(capital_converter.balance_of(accounts.alice), 700);
+            assert_eq!(mock::balance_of(accounts.alice), 600);
+            // the rate stays frozen as nDot is burnt
+            capital_converter.emergency_exit(700);
+            assert_eq!(mock::balance_of(accounts.alice), 2000);
+
+            capital_converter.set_post_mortem(String::from("oracle failure"));
+            assert_eq!(
+                capital_converter.post_mortem(),
+                Some(String::from("oracle failure"))
+            );
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "not guardian")]
+        fn shutdown_not_guardian_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            capital_converter.set_guardian(accounts.bob);
+            capital_converter.owner = accounts.charlie;
+
+            capital_converter.shutdown();
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "already shut down")]
+        fn shutdown_twice_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+
+            capital_converter.shutdown();
+            capital_converter.shutdown();
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "CapitalConverter: shut down")]
+        fn convert_after_shutdown_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
             );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
 
-            let value = amount * self.smart_balance() / self.total_supply();
-            if self.token != self.dot {
-                assert!(
-                    self.token_contract.transfer(caller, value).is_ok(),
-                    "transfer operation did not succeed"
-                );
-            } else {
-                assert!(
-                    self.env().transfer(caller, value).is_ok(),
-                    "transfer operation did not succeed"
-                );
-            }
+            mock::set_balance(accounts.alice, 1000);
+            capital_converter.shutdown();
 
-            assert!(
-                self.burn(caller, amount).is_ok(),
-                "burn operation did not succeed"
+            let _ = capital_converter.convert(1000);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "CapitalConverter: shut down")]
+        fn payouts_after_shutdown_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
             );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
 
-            self.env().emit_event(Burn {
-                sender: Some(caller),
-                amount,
-                output: value,
-            });
+            mock::set_balance(accounts.alice, 1000);
+            assert_eq!(capital_converter.convert(1000), Ok(()));
+            capital_converter.set_operator(accounts.alice);
+            capital_converter.shutdown();
+
+            capital_converter.payouts(1, accounts.bob, 100);
         }
 
-        #[ink(message)]
-        pub fn payouts(&mut self, to: AccountId, amount: Balance) {
-            self.only_operator();
+        #[ink::test]
+        fn emergency_exit_lock_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
 
-            assert!(to != Default::default(), "to is zero");
-            if self.token != self.dot {
-                assert!(
-                    self.token_contract.transfer(to, amount).is_ok(),
-                    "transfer operation did not succeed"
-                );
-            } else {
-                assert!(
-                    self.env().transfer(to, amount).is_ok(),
-                    "transfer operation did not succeed"
-                );
-            }
+            capital_converter.set_lock_blocks(10);
+            mock::set_balance(accounts.alice, 1000);
+            assert_eq!(capital_converter.convert(1000), Ok(()));
+            assert_eq!(capital_converter.locked_balance(accounts.alice), 1000);
+            capital_converter.shutdown();
 
-            self.env().emit_event(Payouts {
-                to: Some(to),
-                amount,
-            });
+            capital_converter.emergency_exit(600);
+            assert_eq!(capital_converter.balance_of(accounts.alice), 400);
+            assert_eq!(capital_converter.locked_balance(accounts.alice), 400);
+
+            capital_converter.emergency_exit(400);
+            assert_eq!(capital_converter.locked_balance(accounts.alice), 0);
+            assert_eq!(capital_converter.redeemable_at(accounts.alice), 0);
+            assert_eq!(mock::balance_of(accounts.alice), 1000);
         }
 
-        #[ink(message)]
-        pub fn set_operator(&mut self, operator: AccountId) {
-            self.only_owner();
-            assert!(operator != Default::default(), "operator is zero");
-            self.operator = operator;
-            self.env().emit_event(SetOperator { operator });
+        #[ink::test]
+        fn losses_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            mock::set_balance(accounts.alice, 1000);
+            assert_eq!(capital_converter.convert(1000), Ok(()));
+            capital_converter.set_operator(accounts.alice);
+            for claim_id in 0..3 {
+                capital_converter.payouts(claim_id, accounts.bob, 100);
+            }
+            assert_eq!(mock::balance_of(accounts.bob), 300);
+
+            assert_eq!(capital_converter.loss_count(), 3);
+            assert_eq!(capital_converter.total_loss(), 300);
+            let page = capital_converter.losses(1, 5);
+            assert_eq!(page.len(), 2);
+            assert_eq!(page[0].claim_id, 1);
+            assert_eq!(page[0].rate_before, 9 * RATE_PRECISION / 10);
+            assert_eq!(page[0].rate_after, 8 * RATE_PRECISION / 10);
+            assert_eq!(page[1].claim_id, 2);
+            assert!(capital_converter.losses(3, 5).is_empty());
+
+            assert_eq!(capital_converter.epoch_loss(0), 300);
+            assert_eq!(capital_converter.epoch_loss(1), 0);
         }
 
-        #[ink(message)]
-        pub fn set_max_convert(&mut self, max: Balance) {
-            self.only_owner();
-            self.max_convert = max;
-            self.env().emit_event(SetMaxConvert { max });
+        #[ink::test]
+        fn lock_follows_transfer_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            assert_eq!(capital_converter.mint(accounts.alice, 1000), Ok(()));
+            capital_converter.lock_shares(accounts.alice, 800, 5);
+            assert_eq!(capital_converter.locked_balance(accounts.alice), 800);
+
+            // the 200 unlocked nDot go first
+            assert_eq!(capital_converter.transfer(accounts.bob, 200), Ok(()));
+            assert_eq!(capital_converter.locked_balance(accounts.bob), 0);
+
+            assert_eq!(capital_converter.transfer(accounts.bob, 300), Ok(()));
+            assert_eq!(capital_converter.locked_balance(accounts.alice), 500);
+            assert_eq!(capital_converter.locked_balance(accounts.bob), 300);
+            assert_eq!(capital_converter.redeemable_at(accounts.bob), 5);
         }
 
-        /// Contract owner.
-        #[ink(message)]
-        pub fn owner(&self) -> Option<AccountId> {
-            Some(self.owner)
+        #[ink::test]
+        #[should_panic(expected = "lock too long")]
+        fn set_lock_blocks_above_max_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+
+            capital_converter.set_lock_blocks(MAX_LOCK_BLOCKS);
+            assert_eq!(capital_converter.lock_blocks(), MAX_LOCK_BLOCKS);
+            capital_converter.set_lock_blocks(MAX_LOCK_BLOCKS + 1);
         }
 
-        /// transfer contract ownership to new owner.
-        #[ink(message)]
-        pub fn transfer_ownership(&mut self, new_owner: Option<AccountId>) {
-            self.only_owner();
-            if let Some(owner) = new_owner {
-                self.owner = owner;
-            }
+        #[ink::test]
+        fn add_strategy_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+
+            capital_converter.add_strategy(AccountId::from([0x03; 32]), 1000);
+            capital_converter.set_debt_ceiling(AccountId::from([0x03; 32]), 2000);
+
+            assert_eq!(capital_converter.strategies(), vec![AccountId::from([0x03; 32])]);
+            assert_eq!(
+                capital_converter.strategy_params(AccountId::from([0x03; 32])),
+                Some(StrategyParams {
+                    debt_ceiling: 2000,
+                    debt: 0,
+                })
+            );
         }
 
-        #[ink(message)]
-        pub  fn set_dot(&mut self,new_dot:AccountId){
-            self.dot = new_dot;
+        #[ink::test]
+        fn ensure_liquidity_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+            let strategy = AccountId::from([0x03; 32]);
+            let contract = ink_env::account_id::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get contract id");
+
+            mock::set_balance(accounts.alice, 1000);
+            assert_eq!(capital_converter.convert(1000), Ok(()));
+            capital_converter.set_operator(accounts.alice);
+            capital_converter.set_max_allocation(BPS_DENOMINATOR);
+            capital_converter.add_strategy(strategy, 1000);
+            capital_converter.allocate(strategy, 600);
+            assert_eq!(mock::balance_of(contract), 400);
+            assert_eq!(capital_converter.smart_balance(), 1000);
+
+            ink_env::test::advance_block::<ink_env::DefaultEnvironment>()
+                .expect("Cannot advance block");
+            // the idle 400 can't pay the exit, the rest is pulled back from the strategy
+            assert_eq!(capital_converter.exit(700), Ok(()));
+            assert_eq!(mock::balance_of(accounts.alice), 700);
+            assert_eq!(mock::balance_of(strategy), 300);
+            assert_eq!(capital_converter.total_debt(), 300);
+            assert_eq!(
+                capital_converter.strategy_params(strategy),
+                Some(StrategyParams {
+                    debt_ceiling: 1000,
+                    debt: 300,
+                })
+            );
+            assert_eq!(capital_converter.iou_total(), 0);
+
+            // the strategy lost half its capital, what it can't return is queued as an IOU
+            mock::set_balance(strategy, 150);
+            assert_eq!(capital_converter.smart_balance(), 150);
+            mock::set_balance(contract, 50);
+            capital_converter.pay_or_issue_iou(accounts.bob, 300);
+            assert_eq!(mock::balance_of(accounts.bob), 200);
+            assert_eq!(capital_converter.iou_balance(accounts.bob), 100);
+            assert_eq!(capital_converter.total_debt(), 150);
         }
-        fn only_owner(&self) {
-            assert_eq!(self.env().caller(), self.owner);
+
+        #[ink::test]
+        #[should_panic(expected = "exceeding the debt ceiling")]
+        fn debt_ceiling_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+            let strategy = AccountId::from([0x03; 32]);
+
+            mock::set_balance(accounts.alice, 1000);
+            assert_eq!(capital_converter.convert(1000), Ok(()));
+            capital_converter.set_operator(accounts.alice);
+            capital_converter.set_max_allocation(BPS_DENOMINATOR);
+            capital_converter.add_strategy(strategy, 500);
+            capital_converter.allocate(strategy, 500);
+            assert_eq!(capital_converter.total_debt(), 500);
+
+            capital_converter.allocate(strategy, 1);
         }
 
-        fn only_operator(&self) {
-            assert!(self.env().caller() == self.operator, "not operator");
+        #[ink::test]
+        fn locked_profit_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            capital_converter.set_reporter(accounts.alice);
+            capital_converter.set_profit_unlock(10);
+            capital_converter.report_profit(1000);
+            assert_eq!(capital_converter.locked_profit(), 1000);
+
+            for _ in 0..5 {
+                ink_env::test::advance_block::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot advance block");
+            }
+            assert_eq!(capital_converter.locked_profit(), 500);
+
+            for _ in 0..5 {
+                ink_env::test::advance_block::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot advance block");
+            }
+            assert_eq!(capital_converter.locked_profit(), 0);
         }
-    }
 
-    /// Unit tests in Rust are normally defined within such a `#[cfg(test)]`
-  /// module and test functions are marked with a `#[test]` attribute.
-  /// The below code is technically just normal Rust code.
-    #[cfg(test)]
-    mod tests {
-        /// Imports all the definitions from the outer scope so we can use them here.
-        use super::*;
+        #[ink::test]
+        fn rate_twap_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
 
-        /// Imports `ink_lang` so we can use `#[ink::test]`.
-        use ink_lang as ink;
-        use ink_env::AccountId;
+            capital_converter.push_observation(0, RATE_PRECISION);
+            capital_converter.push_observation(10, 2 * RATE_PRECISION);
+
+            assert_eq!(capital_converter.twap_at(20, 10), Some(2 * RATE_PRECISION));
+            assert_eq!(capital_converter.twap_at(20, 20), Some(3 * RATE_PRECISION / 2));
+            assert_eq!(capital_converter.twap_at(20, 21), None);
+            assert_eq!(capital_converter.twap_at(20, 0), None);
+            assert_eq!(capital_converter.rate_twap(0), None);
+        }
 
         #[ink::test]
-        fn mint_test() {
+        fn transfer_iou_test() {
             let mut capital_converter = CapitalConverter::new(
                 None,
                 None,
@@ -529,15 +2903,46 @@ mod capital_converter {
                 ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
                     .expect("Cannot get accounts");
 
-            assert_eq!(capital_converter.mint(accounts.alice, 1000), Ok(()));
+            capital_converter.issue_iou(accounts.alice, 300);
+            capital_converter.issue_iou(accounts.alice, 200);
+            assert_eq!(capital_converter.iou_balance(accounts.alice), 500);
+            assert_eq!(capital_converter.iou_total(), 500);
+            assert_eq!(capital_converter.iou_queue(), (0, 2));
+
+            capital_converter.transfer_iou(1, accounts.bob);
+            assert_eq!(capital_converter.iou_balance(accounts.alice), 300);
+            assert_eq!(capital_converter.iou_balance(accounts.bob), 200);
+            assert_eq!(
+                capital_converter.iou(1),
+                Some(Iou {
+                    owner: accounts.bob,
+                    amount: 200,
+                })
+            );
+        }
 
-            assert_eq!(*capital_converter.total_supply, 1000);
+        #[ink::test]
+        fn minimum_capital_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
 
-            assert_eq!(capital_converter.balance_of(accounts.alice), 1000);
+            capital_converter.set_cover(accounts.alice);
+            capital_converter.set_locked_capital(1000);
+            assert_eq!(capital_converter.minimum_capital(), 1000);
+
+            capital_converter.set_mcr_ratio(15000);
+            assert_eq!(capital_converter.minimum_capital(), 1500);
         }
 
         #[ink::test]
-        fn burn_test() {
+        fn set_approver_test() {
             let mut capital_converter = CapitalConverter::new(
                 None,
                 None,
@@ -548,17 +2953,38 @@ mod capital_converter {
                 ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
                     .expect("Cannot get accounts");
 
-            assert_eq!(capital_converter.mint(accounts.alice, 1000), Ok(()));
+            assert_eq!(capital_converter.is_approver(accounts.bob), false);
 
-            assert_eq!(capital_converter.burn(accounts.alice, 200), Ok(()));
+            capital_converter.set_approver(accounts.bob, true);
+            assert_eq!(capital_converter.is_approver(accounts.bob), true);
 
-            assert_eq!(capital_converter.balance_of(accounts.alice), 800);
+            capital_converter.set_approver(accounts.bob, false);
+            assert_eq!(capital_converter.is_approver(accounts.bob), false);
+        }
 
-            assert_eq!(*capital_converter.total_supply, 800);
+        #[ink::test]
+        fn set_fees_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            capital_converter.set_treasury(accounts.eve);
+            capital_converter.set_fees(30, 50);
+
+            assert_eq!(capital_converter.convert_fee_bps(), 30);
+            assert_eq!(capital_converter.exit_fee_bps(), 50);
+            assert_eq!(CapitalConverter::fee_of(10000, 30), 30);
         }
 
         #[ink::test]
-        fn transfer_test() {
+        #[should_panic(expected = "fee too high")]
+        fn set_fees_above_max_test() {
             let mut capital_converter = CapitalConverter::new(
                 None,
                 None,
@@ -569,19 +2995,62 @@ mod capital_converter {
                 ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
                     .expect("Cannot get accounts");
 
-            assert_eq!(capital_converter.mint(accounts.alice, 1000), Ok(()));
+            capital_converter.set_treasury(accounts.eve);
+            capital_converter.set_fees(MAX_FEE_BPS + 1, 0);
+        }
 
-            assert_eq!(capital_converter.balance_of(accounts.bob), 0);
+        #[ink::test]
+        fn deposit_limits_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
 
-            assert_eq!(capital_converter.transfer(accounts.bob, 100), Ok(()));
+            capital_converter.set_deposit_limits(5000, 1000);
+            assert_eq!(
+                capital_converter.check_deposit_limits(accounts.bob, 1000, 0),
+                Ok(())
+            );
+            assert_eq!(
+                capital_converter.check_deposit_limits(accounts.bob, 1001, 0),
+                Err(Error::ExceedsMaxPerAccount)
+            );
+            assert_eq!(
+                capital_converter.check_deposit_limits(accounts.bob, 1000, 4500),
+                Err(Error::ExceedsMaxTotalAssets)
+            );
 
-            assert_eq!(capital_converter.balance_of(accounts.bob), 100);
+            // the cap follows what bob converted, not the nDot bob still holds
+            capital_converter.net_deposits.insert(accounts.bob, 600);
+            assert_eq!(
+                capital_converter.check_deposit_limits(accounts.bob, 400, 0),
+                Ok(())
+            );
+            assert_eq!(
+                capital_converter.check_deposit_limits(accounts.bob, 401, 0),
+                Err(Error::ExceedsMaxPerAccount)
+            );
+            capital_converter.net_deposits.insert(accounts.bob, 0);
 
-            assert_eq!(capital_converter.balance_of(accounts.alice), 900);
+            capital_converter.set_allowlist_enabled(true);
+            assert_eq!(
+                capital_converter.check_deposit_limits(accounts.bob, 1000, 0),
+                Err(Error::NotAllowlisted)
+            );
+            capital_converter.set_allowlisted(accounts.bob, true);
+            assert_eq!(
+                capital_converter.check_deposit_limits(accounts.bob, 1000, 0),
+                Ok(())
+            );
         }
 
         #[ink::test]
-        fn allowance_test() {
+        fn zapper_limits_test() {
             let mut capital_converter = CapitalConverter::new(
                 None,
                 None,
@@ -592,12 +3061,49 @@ mod capital_converter {
                 ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
                     .expect("Cannot get accounts");
 
-            assert_eq!(capital_converter.mint(accounts.alice, 1000), Ok(()));
+            // alice is the zapper converting for charlie
+            capital_converter.set_zapper(accounts.alice);
+            mock::set_balance(accounts.alice, 1000);
+            capital_converter.set_allowlist_enabled(true);
+            capital_converter.set_allowlisted(accounts.alice, true);
+            assert_eq!(
+                capital_converter.convert_on_behalf(accounts.charlie, 100),
+                Err(Error::NotAllowlisted)
+            );
 
-            assert_eq!(capital_converter.approve(accounts.bob, 100), Ok(()));
+            capital_converter.set_allowlisted(accounts.charlie, true);
+            capital_converter.set_deposit_limits(Balance::MAX, 150);
+            assert_eq!(capital_converter.convert_on_behalf(accounts.charlie, 100), Ok(()));
+            assert_eq!(capital_converter.balance_of(accounts.alice), 100);
+            assert_eq!(capital_converter.net_deposit_of(accounts.charlie), 100);
+            assert_eq!(capital_converter.net_deposit_of(accounts.alice), 0);
+            assert_eq!(
+                capital_converter.convert_on_behalf(accounts.charlie, 100),
+                Err(Error::ExceedsMaxPerAccount)
+            );
 
-            assert_eq!(capital_converter.allowance(accounts.alice, accounts.bob), 100);
+            ink_env::test::advance_block::<ink_env::DefaultEnvironment>()
+                .expect("Cannot advance block");
+            assert_eq!(capital_converter.exit_on_behalf(accounts.charlie, 100), Ok(()));
+            assert_eq!(capital_converter.net_deposit_of(accounts.charlie), 0);
+            assert_eq!(mock::balance_of(accounts.alice), 1000);
         }
 
+        #[ink::test]
+        #[should_panic(expected = "not zapper")]
+        fn convert_on_behalf_not_zapper_test() {
+            let mut capital_converter = CapitalConverter::new(
+                None,
+                None,
+                8,
+                AccountId::from([0x00; 32]),
+            );
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            capital_converter.set_zapper(accounts.bob);
+            let _ = capital_converter.convert_on_behalf(accounts.charlie, 100);
+        }
     }
 }
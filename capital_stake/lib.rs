@@ -1,17 +1,25 @@
 #![cfg_attr(not(feature = "std"), no_std)]
 
+pub use self::capital_stake::CapitalStake;
 use ink_lang as ink;
 
 #[ink::contract]
 mod capital_stake {
     use erc20::Erc20;
-    use ink_env::call::FromAccountId;
-    use ink_prelude::{vec, vec::Vec};
-    use ink_storage::{
-        collections::HashMap as StorageHashMap,
-        lazy::Lazy,
-        traits::{PackedLayout, SpreadLayout},
+    use ink_env::{
+        call::FromAccountId,
+        hash::{Blake2x256, HashOutput},
+    };
+    #[cfg(not(test))]
+    use ink_env::{
+        call::{build_call, utils::ReturnType, ExecutionInput, Selector},
+        DefaultEnvironment,
     };
+    use ink_prelude::{vec, vec::Vec};
+    use ink_storage::traits::{PackedLayout, SpreadLayout};
+
+    #[cfg(not(feature = "ink-as-dependency"))]
+    use ink_storage::{collections::HashMap as StorageHashMap, lazy::Lazy};
 
     #[ink(event)]
     pub struct Deposit {
@@ -29,6 +37,12 @@ mod capital_stake {
         operator: AccountId,
     }
 
+    #[ink(event)]
+    pub struct SetZapper {
+        #[ink(topic)]
+        zapper: AccountId,
+    }
+
     #[ink(event)]
     pub struct SetSigner {
         #[ink(topic)]
@@ -41,6 +55,12 @@ mod capital_stake {
         swi: bool,
     }
 
+    #[ink(event)]
+    pub struct SetEmergency {
+        #[ink(topic)]
+        emergency: bool,
+    }
+
     #[ink(event)]
     pub struct SetUserCapacityMax {
         #[ink(topic)]
@@ -63,6 +83,46 @@ mod capital_stake {
         reward: Balance,
     }
 
+    #[ink(event)]
+    pub struct AddEmissionSegment {
+        #[ink(topic)]
+        start_block: BlockNumber,
+        #[ink(topic)]
+        rate: Balance,
+    }
+
+    #[ink(event)]
+    pub struct SetHalvingInterval {
+        #[ink(topic)]
+        blocks: BlockNumber,
+    }
+
+    #[ink(event)]
+    pub struct SetEndBlock {
+        #[ink(topic)]
+        block: BlockNumber,
+    }
+
+    #[ink(event)]
+    pub struct SetRewardSource {
+        #[ink(topic)]
+        source: RewardSource,
+    }
+
+    #[ink(event)]
+    pub struct SetMintCap {
+        #[ink(topic)]
+        cap: Balance,
+    }
+
+    #[ink(event)]
+    pub struct FundReserve {
+        #[ink(topic)]
+        funder: AccountId,
+        #[ink(topic)]
+        amount: Balance,
+    }
+
     #[ink(event)]
     pub struct UpdateWithdrawPending {
         #[ink(topic)]
@@ -129,12 +189,110 @@ mod capital_stake {
         amount: Balance,
     }
 
+    #[ink(event)]
+    pub struct EmergencyWithdraw {
+        #[ink(topic)]
+        user: AccountId,
+        #[ink(topic)]
+        pid: u32,
+        #[ink(topic)]
+        amount: Balance,
+    }
+
+    #[ink(event)]
+    pub struct DepositLocked {
+        #[ink(topic)]
+        user: AccountId,
+        #[ink(topic)]
+        pid: u32,
+        #[ink(topic)]
+        amount: Balance,
+        #[ink(topic)]
+        unlock_at: BlockNumber,
+    }
+
+    #[ink(event)]
+    pub struct EarlyExit {
+        #[ink(topic)]
+        user: AccountId,
+        #[ink(topic)]
+        pid: u32,
+        #[ink(topic)]
+        amount: Balance,
+        #[ink(topic)]
+        penalty: Balance,
+    }
+
+    #[ink(event)]
+    pub struct SetBoostCurve {
+        #[ink(topic)]
+        max_lock_blocks: BlockNumber,
+        #[ink(topic)]
+        max_boost: u128,
+    }
+
+    #[ink(event)]
+    pub struct SetEarlyExitPenalty {
+        #[ink(topic)]
+        penalty: u128,
+    }
+
+    #[ink(event)]
+    pub struct SetTreasury {
+        #[ink(topic)]
+        treasury: AccountId,
+    }
+
+    #[ink(event)]
+    pub struct ClaimExtraReward {
+        #[ink(topic)]
+        user: AccountId,
+        #[ink(topic)]
+        pid: u32,
+        #[ink(topic)]
+        token: AccountId,
+        #[ink(topic)]
+        amount: Balance,
+    }
+
+    #[ink(event)]
+    pub struct AddExtraReward {
+        #[ink(topic)]
+        pid: u32,
+        #[ink(topic)]
+        token: AccountId,
+        #[ink(topic)]
+        rate: Balance,
+    }
+
+    #[ink(event)]
+    pub struct SetExtraRewardRate {
+        #[ink(topic)]
+        pid: u32,
+        #[ink(topic)]
+        token: AccountId,
+        #[ink(topic)]
+        rate: Balance,
+    }
+
+    #[ink(event)]
+    pub struct FundExtraReward {
+        #[ink(topic)]
+        pid: u32,
+        #[ink(topic)]
+        token: AccountId,
+        #[ink(topic)]
+        funder: AccountId,
+        #[ink(topic)]
+        amount: Balance,
+    }
+
     // pub const NAME: String = "CapitalStake".to_string();
     // pub const VERSION: String = "1".to_string();
 
-    // Info of each user.
+    // Info of each user in a pool.
     #[derive(
-        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, Default, SpreadLayout, PackedLayout,
     )]
     #[cfg_attr(
         feature = "std",
@@ -143,6 +301,8 @@ mod capital_stake {
     pub struct UserInfo {
         // How many  tokens the user has provided.
         pub amount: Balance,
+        // amount with the locked part boosted, rewards are shared by weight
+        pub weight: Balance,
         // Reward debt. See explanation below.
         pub reward_debt: u128,
         pub reward: Balance,
@@ -151,6 +311,93 @@ mod capital_stake {
         pub pending_at: u64,
     }
 
+    // Staked tokens of a user in a pool that cannot be unstaked before `unlock_at`,
+    // their weight is boosted by `boost` in basis points.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct StakeLock {
+        pub amount: Balance,
+        pub unlock_at: BlockNumber,
+        pub boost: u128,
+    }
+
+    // Boost in basis points of a lock of `lock_blocks`, interpolated linearly between points.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct BoostPoint {
+        pub lock_blocks: BlockNumber,
+        pub boost: u128,
+    }
+
+    // Unstaked tokens that can be withdrawn from `unlock_at` on.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct UnbondRequest {
+        pub amount: Balance,
+        pub unlock_at: u64,
+    }
+
+    // NSURE emitted per block from `start_block` on, until the next segment starts.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct EmissionSegment {
+        pub start_block: BlockNumber,
+        pub rate: Balance,
+    }
+
+    // Where the NSURE rewards come from.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub enum RewardSource {
+        // minted by this contract, up to `mint_cap` in total
+        Mint,
+        // taken from the reserve funded through `fund_reserve`
+        Reserve,
+    }
+
+    // Reward token paid to the stakers of a pool besides NSURE, e.g. by a partner protocol.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct ExtraReward {
+        pub token: AccountId,
+        // paid per block to the whole pool while the reserve lasts
+        pub rate: Balance,
+        pub acc_per_share: u128,
+        pub last_reward_block: BlockNumber,
+        // funded and not yet credited to the stakers
+        pub reserve: Balance,
+    }
+
     // Info of each pool.
     #[derive(
         Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, SpreadLayout, PackedLayout,
@@ -160,25 +407,59 @@ mod capital_stake {
         derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
     )]
     pub struct PoolInfo {
-        //Total Deposit of token
+        // Total staked token earning rewards, without the pending withdrawals
         pub amount: Balance,
+        // Total weight of the stakers, rewards are shared by weight
+        pub total_weight: Balance,
         // Address of token contract.
         pub lp_token: AccountId,
         pub alloc_point: u128,
         pub last_reward_block: BlockNumber,
         pub acc_nsure_per_share: u128,
+        // Total unstaked token waiting for withdrawal, earns no rewards
         pub pending: u128,
+        // Reward tokens besides NSURE.
+        pub extra_rewards: Vec<ExtraReward>,
     }
 
+    /// Selector of the erc20 `transfer` message, the first four bytes of `BLAKE2("transfer")`.
+    pub const TRANSFER_SELECTOR: [u8; 4] = [0x84, 0xA1, 0x5D, 0xA1];
+
+    /// Max reward tokens of a pool besides NSURE.
+    pub const MAX_EXTRA_REWARDS: usize = 4;
+    /// Denominator of ratios expressed in basis points.
+    pub const BPS_DENOMINATOR: u128 = 10000;
+    /// Hard cap on the lock boost, 5x.
+    pub const MAX_BOOST: u128 = 50000;
+
+    /// Max unbond requests of a user in a pool waiting for withdrawal.
+    pub const MAX_UNBOND_REQUESTS: usize = 32;
+
     #[ink(storage)]
     pub struct CapitalStake {
         signer: AccountId,
         nsure: Lazy<Erc20>,
-        nsure_per_block: Balance,
+        nsure_token: AccountId,
+        // emission segments ordered by start block
+        emission_schedule: Vec<EmissionSegment>,
+        // the rate of a segment halves every `halving_interval` blocks from its start, 0 for never
+        halving_interval: BlockNumber,
+        // no emission from this block on
+        end_block: BlockNumber,
+        reward_source: RewardSource,
+        // max NSURE minted in total as rewards, and minted so far
+        mint_cap: Balance,
+        minted: Balance,
+        // funded NSURE not yet credited to the pools
+        reward_reserve: Balance,
+        // NSURE credited to the pools and not yet paid out
+        reward_debt_total: Balance,
         // 14 Days
         pending_duration: u64,
         capacity_max: StorageHashMap<u32, Balance>,
         can_deposit: bool,
+        // set by the owner when rewards can't be paid, allows emergency_withdraw
+        emergency: bool,
         operator: AccountId,
         // the max capacity for one user's deposit.
         user_capacity_max: StorageHashMap<u32, Balance>,
@@ -186,48 +467,87 @@ mod capital_stake {
         pool_info: Vec<PoolInfo>,
         /// @notice A record of states for signing / validating signatures
         nonces: StorageHashMap<AccountId, u128>,
+        // Info of each user in each pool, by pool id and user.
         user_info: StorageHashMap<(u32, AccountId), UserInfo>,
+        // Reward debt of each user for the extra reward tokens, by pool id, token and user.
+        extra_reward_debt: StorageHashMap<(u32, AccountId, AccountId), u128>,
+        // Extra rewards whose transfer failed, paid on the next settlement, by pool id, token and user.
+        extra_reward_owed: StorageHashMap<(u32, AccountId, AccountId), Balance>,
+        // Unbond requests of each user in each pool, oldest first.
+        unbond_requests: StorageHashMap<(u32, AccountId), Vec<UnbondRequest>>,
         // Total allocation poitns. Must be the sum of all allocation points in all pools.
         total_alloc_point: u128,
         start_block: BlockNumber,
         owner: AccountId,
-//user info
-         amount: StorageHashMap<AccountId, Balance>,
-        // Reward debt. See explanation below.
-         reward_debt: StorageHashMap<AccountId, Balance>,
-         reward: StorageHashMap<AccountId, Balance>,
-        // payments available for withdrawal by an investor
-         pending_withdrawal: StorageHashMap<AccountId, Balance>,
-         pending_at: StorageHashMap<AccountId, u64>,
+        // router allowed to withdraw on behalf of users
+        zapper: AccountId,
+        // locked stake of each user in each pool
+        locks: StorageHashMap<(u32, AccountId), StakeLock>,
+        // lock boost curve, from lock 0 at 1x up to the max lock
+        boost_curve: Vec<BoostPoint>,
+        // share of the locked stake taken on early exit, in basis points
+        early_exit_penalty: u128,
+        // receives the early exit penalties
+        treasury: AccountId,
     }
 
     impl CapitalStake {
+        /// `mint_cap` is the max NSURE minted as rewards, without it nothing is earned
+        /// until `set_mint_cap`, or `set_reward_source` and `fund_reserve`.
         #[ink(constructor)]
-        pub fn new(signer: AccountId, nsure: AccountId, start_block: BlockNumber) -> Self {
+        pub fn new(
+            signer: AccountId,
+            nsure: AccountId,
+            start_block: BlockNumber,
+            mint_cap: Balance,
+        ) -> Self {
+            let nsure_token = nsure;
             let nsure: Erc20 = FromAccountId::from_account_id(nsure);
             Self {
                 signer,
                 nsure: Lazy::new(nsure),
-                nsure_per_block: 18 * 10u128.saturating_pow(10),
+                nsure_token,
+                emission_schedule: vec![EmissionSegment {
+                    start_block: 0,
+                    rate: 18 * 10u128.saturating_pow(10),
+                }],
+                halving_interval: 0,
+                end_block: BlockNumber::MAX,
+                reward_source: RewardSource::Mint,
+                mint_cap,
+                minted: 0,
+                reward_reserve: 0,
+                reward_debt_total: 0,
                 capacity_max: StorageHashMap::new(),
                 operator: Default::default(),
                 user_capacity_max: StorageHashMap::new(),
                 nonces: StorageHashMap::new(),
                 user_info: StorageHashMap::new(),
+                extra_reward_debt: StorageHashMap::new(),
+                extra_reward_owed: StorageHashMap::new(),
+                unbond_requests: StorageHashMap::new(),
                 total_alloc_point: 0,
                 pending_duration: 10,
                 can_deposit: true,
+                emergency: false,
                 pool_info: vec![],
                 start_block,
                 owner: Self::env().caller(),
-
-                amount: StorageHashMap::new(),
-                // Reward debt. See explanation below.
-                reward_debt: StorageHashMap::new(),
-                reward: StorageHashMap::new(),
-                // payments available for withdrawal by an investor
-                pending_withdrawal: StorageHashMap::new(),
-                pending_at: StorageHashMap::new(),
+                zapper: Default::default(),
+                locks: StorageHashMap::new(),
+                // 2.5x for a year of 6s blocks
+                boost_curve: vec![
+                    BoostPoint {
+                        lock_blocks: 0,
+                        boost: BPS_DENOMINATOR,
+                    },
+                    BoostPoint {
+                        lock_blocks: 5_256_000,
+                        boost: 25000,
+                    },
+                ],
+                early_exit_penalty: 1000,
+                treasury: Self::env().caller(),
             }
         }
 
@@ -239,14 +559,9 @@ mod capital_stake {
         }
 
         #[ink(message)]
-        pub fn get_user_info(&self,user:AccountId) ->(Balance,Balance,Balance,Balance,u64) {
-            let  amount = self.amount.get(&user).map(|i|*i).unwrap_or(0u128);
-            let  reward_debt = self.reward_debt.get(&user).map(|i|*i).unwrap_or(0u128);
-            let  pending_withdrawal_info = self.pending_withdrawal.get(&user).map(|i|*i).unwrap_or(0u128);
-            let  reward = self.reward.get(&user).map(|i|*i).unwrap_or(0u128);
-
-            let  pending_at_info = self.pending_at.get(&user).map(|i|*i).unwrap_or(0u64);
-            return (amount,reward_debt,pending_withdrawal_info,reward,pending_at_info);
+        pub fn get_user_info(&self, pid: u32, user: AccountId) -> (Balance, Balance, Balance, Balance, u64) {
+            let info = self.user_info_of(pid, user);
+            (info.amount, info.reward_debt, info.pending_withdrawal, info.reward, info.pending_at)
         }
 
         #[ink(message)]
@@ -271,6 +586,19 @@ mod capital_stake {
             self.env().emit_event(SetSigner { signer });
         }
 
+        // allows emergency_withdraw while rewards can't be paid.
+        #[ink(message)]
+        pub fn set_emergency(&mut self, emergency: bool) {
+            self.only_owner();
+            self.emergency = emergency;
+            self.env().emit_event(SetEmergency { emergency });
+        }
+
+        #[ink(message)]
+        pub fn emergency(&self) -> bool {
+            self.emergency
+        }
+
         #[ink(message)]
         pub fn switch_deposit(&mut self) {
             self.only_owner();
@@ -294,13 +622,148 @@ mod capital_stake {
             self.env().emit_event(SetCapacityMax { pid, max });
         }
 
+        // Changes the emission rate from the current block on. A segment scheduled for
+        // later has to be replaced with `add_emission_segment` instead.
         #[ink(message)]
         pub fn update_block_reward(&mut self, reward: Balance) {
             self.only_owner();
-            self.nsure_per_block = reward;
+            let block_number = self.env().block_number();
+            let last = self.emission_schedule.last().unwrap().start_block;
+            assert!(last <= block_number, "segment scheduled");
+            self.set_emission_segment(block_number, reward);
             self.env().emit_event(UpdateBlockReward { reward });
         }
 
+        // Schedules the emission `rate` from `start_block` on, which cannot be in the past.
+        // A segment starting at the same block as the last one replaces it.
+        #[ink(message)]
+        pub fn add_emission_segment(&mut self, start_block: BlockNumber, rate: Balance) {
+            self.only_owner();
+            assert!(start_block >= self.env().block_number(), "start in the past");
+            let last = self.emission_schedule.last().unwrap().start_block;
+            assert!(start_block >= last, "start before the last segment");
+            self.set_emission_segment(start_block, rate);
+        }
+
+        #[ink(message)]
+        pub fn set_halving_interval(&mut self, blocks: BlockNumber) {
+            self.only_owner();
+            self.mass_update_pools();
+            self.halving_interval = blocks;
+            self.env().emit_event(SetHalvingInterval { blocks });
+        }
+
+        #[ink(message)]
+        pub fn set_end_block(&mut self, block: BlockNumber) {
+            self.only_owner();
+            let block_number = self.env().block_number();
+            assert!(block >= block_number, "end in the past");
+            assert!(self.end_block > block_number, "emission already ended");
+            self.mass_update_pools();
+            self.end_block = block;
+            self.env().emit_event(SetEndBlock { block });
+        }
+
+        #[ink(message)]
+        pub fn set_reward_source(&mut self, source: RewardSource) {
+            self.only_owner();
+            self.mass_update_pools();
+            self.reward_source = source;
+            self.env().emit_event(SetRewardSource { source });
+        }
+
+        // Sets the max NSURE minted in total when rewards are minted.
+        #[ink(message)]
+        pub fn set_mint_cap(&mut self, cap: Balance) {
+            self.only_owner();
+            self.mass_update_pools();
+            self.mint_cap = cap;
+            self.env().emit_event(SetMintCap { cap });
+        }
+
+        // Adds `amount` NSURE of the caller to the reward reserve.
+        #[ink(message)]
+        pub fn fund_reserve(&mut self, amount: Balance) {
+            assert!(amount > 0, "invalid amount");
+            let caller = self.env().caller();
+            assert!(self.token_transfer_from(self.nsure_token, caller, amount));
+            self.reward_reserve = self.reward_reserve + amount;
+            self.env().emit_event(FundReserve {
+                funder: caller,
+                amount,
+            });
+        }
+
+        #[ink(message)]
+        pub fn reward_source(&self) -> RewardSource {
+            self.reward_source
+        }
+
+        #[ink(message)]
+        pub fn mint_cap(&self) -> Balance {
+            self.mint_cap
+        }
+
+        #[ink(message)]
+        pub fn minted(&self) -> Balance {
+            self.minted
+        }
+
+        #[ink(message)]
+        pub fn reward_reserve(&self) -> Balance {
+            self.reward_reserve
+        }
+
+        // Returns the NSURE owed to stakers, credited to the pools and not yet paid out.
+        #[ink(message)]
+        pub fn reward_debt_total(&self) -> Balance {
+            self.reward_debt_total
+        }
+
+        // Returns the blocks the reward source can sustain at the current emission rate.
+        #[ink(message)]
+        pub fn reward_runway(&self) -> BlockNumber {
+            let rate = self.emission_rate();
+            if rate == 0 {
+                return BlockNumber::MAX;
+            }
+            (self.available_reward() / rate).min(BlockNumber::MAX as u128) as BlockNumber
+        }
+
+        #[ink(message)]
+        pub fn emission_schedule(&self) -> Vec<EmissionSegment> {
+            self.emission_schedule.clone()
+        }
+
+        #[ink(message)]
+        pub fn halving_interval(&self) -> BlockNumber {
+            self.halving_interval
+        }
+
+        #[ink(message)]
+        pub fn end_block(&self) -> BlockNumber {
+            self.end_block
+        }
+
+        // Returns the NSURE emitted per block at the current block.
+        #[ink(message)]
+        pub fn emission_rate(&self) -> Balance {
+            let block_number = self.env().block_number();
+            self.get_multiplier(block_number, block_number + 1)
+        }
+
+        // pools are settled first so the new segment only changes future rewards
+        fn set_emission_segment(&mut self, start_block: BlockNumber, rate: Balance) {
+            self.mass_update_pools();
+            let last = self.emission_schedule.last_mut().unwrap();
+            if last.start_block == start_block {
+                last.rate = rate;
+            } else {
+                self.emission_schedule.push(EmissionSegment { start_block, rate });
+            }
+            self.env().emit_event(AddEmissionSegment { start_block, rate });
+        }
+
         #[ink(message)]
         pub fn update_withdraw_pending(&mut self, seconds: u64) {
             self.only_owner();
@@ -326,10 +789,11 @@ mod capital_stake {
             assert_ne!(lp_token, Default::default(), "lp_token is zero");
 
             for i in 0..self.pool_length() as usize {
-                assert_ne!(
-                    lp_token,
-                    self.pool_info.get(i).unwrap().lp_token,
-                    "Duplicate Token!"
+                let pool = self.pool_info.get(i).unwrap();
+                assert_ne!(lp_token, pool.lp_token, "Duplicate Token!");
+                assert!(
+                    pool.extra_rewards.iter().all(|extra| extra.token != lp_token),
+                    "token is a reward"
                 );
             }
 
@@ -351,11 +815,13 @@ mod capital_stake {
 
             self.pool_info.push(PoolInfo {
                 amount: 0,
+                total_weight: 0,
                 lp_token,
                 alloc_point,
                 last_reward_block,
                 acc_nsure_per_share: 0,
                 pending: 0,
+                extra_rewards: Vec::new(),
             });
 
             self.env().emit_event(Add {
@@ -397,29 +863,125 @@ mod capital_stake {
         }
 
         #[ink(message)]
-        pub fn pending_nsure(& self, pid: u32, user: AccountId) -> u128 {
+        pub fn pending_nsure(&self, pid: u32, user: AccountId) -> u128 {
             let pool: &PoolInfo = self.pool_info.get(pid as usize).unwrap();
-            // let user = self.user_info.get(&(pid, user)).unwrap();
-            // let amount = self.amount.get(&user).unwrap();
-            let  amount = self.amount.get(&user).map(|i|*i).unwrap_or(0u128);
-            // let reward_debt = self.reward_debt.get(&user).unwrap();
-            let  reward_debt = self.reward_debt.get(&user).map(|i|*i).unwrap_or(0u128);
+            let info = self.user_info_of(pid, user);
 
-            let self_account = self.env().account_id();
             let block_number = self.env().block_number();
 
             let mut acc_nsure_per_share = pool.acc_nsure_per_share;
 
-            let lp_token: Erc20 = FromAccountId::from_account_id(pool.lp_token);
-            let lp_supply = lp_token.balance_of(self_account);
+            let lp_supply = pool.total_weight;
             if block_number > pool.last_reward_block && lp_supply != 0 {
-                let multiplier = Self::get_multiplier(pool.last_reward_block, block_number);
-                let nsure_reward =
-                    multiplier * self.nsure_per_block * pool.alloc_point / self.total_alloc_point;
+                let multiplier = self.get_multiplier(pool.last_reward_block, block_number);
+                let nsure_reward = (multiplier * pool.alloc_point / self.total_alloc_point)
+                    .min(self.available_reward());
                 acc_nsure_per_share = acc_nsure_per_share + nsure_reward * 10u128.saturating_pow(12) / lp_supply;
             }
 
-            amount * acc_nsure_per_share / 10u128.saturating_pow(12) - reward_debt
+            info.weight * acc_nsure_per_share / 10u128.saturating_pow(12) - info.reward_debt
+        }
+
+        /// Returns the rewards of `user` in pool `pid` that `claim` would pay,
+        /// NSURE first and then the extra reward tokens.
+        #[ink(message)]
+        pub fn pending_rewards(&self, pid: u32, user: AccountId) -> Vec<(AccountId, Balance)> {
+            let mut rewards = vec![(self.nsure_token, self.pending_nsure(pid, user))];
+
+            let pool: &PoolInfo = self.pool_info.get(pid as usize).unwrap();
+            let weight = self.user_info_of(pid, user).weight;
+            let block_number = self.env().block_number();
+            for extra in pool.extra_rewards.iter() {
+                let mut extra = *extra;
+                Self::accrue_extra_reward(&mut extra, pool.total_weight, block_number);
+                let debt = self
+                    .extra_reward_debt
+                    .get(&(pid, extra.token, user))
+                    .copied()
+                    .unwrap_or(0);
+                rewards.push((
+                    extra.token,
+                    weight * extra.acc_per_share / 10u128.saturating_pow(12) - debt
+                        + self.extra_reward_owed(pid, extra.token, user),
+                ));
+            }
+            rewards
+        }
+
+        /// Returns the extra reward `token` owed to `user` in pool `pid` after a failed
+        /// transfer, paid on the next deposit, unstake or claim.
+        #[ink(message)]
+        pub fn extra_reward_owed(&self, pid: u32, token: AccountId, user: AccountId) -> Balance {
+            self.extra_reward_owed.get(&(pid, token, user)).copied().unwrap_or(0)
+        }
+
+        #[ink(message)]
+        pub fn extra_rewards(&self, pid: u32) -> Vec<ExtraReward> {
+            self.pool_info.get(pid as usize).unwrap().extra_rewards.clone()
+        }
+
+        // Adds `token` as reward of pool `pid`, paid at `rate` per block once funded.
+        #[ink(message)]
+        pub fn add_extra_reward(&mut self, pid: u32, token: AccountId, rate: Balance) {
+            self.only_owner();
+            assert!(pid < self.pool_info.len() as u32, "invalid _pid");
+            assert_ne!(token, Default::default(), "token is zero");
+            assert_ne!(token, self.nsure_token, "token is nsure");
+            // the staked tokens must never be paid out as rewards
+            assert!(
+                self.pool_info.iter().all(|pool| pool.lp_token != token),
+                "token is staked"
+            );
+
+            let block_number = self.env().block_number().max(self.start_block);
+            let pool = self.pool_info.get_mut(pid as usize).unwrap();
+            assert!(pool.extra_rewards.len() < MAX_EXTRA_REWARDS, "too many rewards");
+            assert!(
+                pool.extra_rewards.iter().all(|extra| extra.token != token),
+                "Duplicate Token!"
+            );
+            pool.extra_rewards.push(ExtraReward {
+                token,
+                rate,
+                acc_per_share: 0,
+                last_reward_block: block_number,
+                reserve: 0,
+            });
+            self.env().emit_event(AddExtraReward { pid, token, rate });
+        }
+
+        #[ink(message)]
+        pub fn set_extra_reward_rate(&mut self, pid: u32, index: u32, rate: Balance) {
+            self.only_owner();
+            assert!(pid < self.pool_info.len() as u32, "invalid _pid");
+            self.update_pool(pid);
+            let pool = self.pool_info.get_mut(pid as usize).unwrap();
+            let extra = pool.extra_rewards.get_mut(index as usize).expect("invalid index");
+            extra.rate = rate;
+            let token = extra.token;
+            self.env().emit_event(SetExtraRewardRate { pid, token, rate });
+        }
+
+        // Adds `amount` of the caller's reward token `index` to its reserve in pool `pid`.
+        #[ink(message)]
+        pub fn fund_extra_reward(&mut self, pid: u32, index: u32, amount: Balance) {
+            assert!(pid < self.pool_info.len() as u32, "invalid _pid");
+            assert!(amount > 0, "invalid amount");
+            let caller = self.env().caller();
+            self.update_pool(pid);
+
+            let pool = self.pool_info.get(pid as usize).unwrap();
+            let token = pool.extra_rewards.get(index as usize).expect("invalid index").token;
+            assert!(self.token_transfer_from(token, caller, amount));
+            let pool = self.pool_info.get_mut(pid as usize).unwrap();
+            let extra = &mut pool.extra_rewards[index as usize];
+            extra.reserve = extra.reserve + amount;
+            self.env().emit_event(FundExtraReward {
+                pid,
+                token,
+                funder: caller,
+                amount,
+            });
         }
 
         #[ink(message)]
@@ -430,142 +992,333 @@ mod capital_stake {
 
 
         #[ink(message)]
-        pub fn show_user_info_amount(&self) -> Balance {
+        pub fn show_user_info_amount(&self, pid: u32) -> Balance {
             let caller = self.env().caller();
-            *self.amount.get(&caller).unwrap()
+            self.user_info_of(pid, caller).amount
         }
 
         #[ink(message)]
         pub fn deposit(&mut self, pid: u32, amount: Balance) {
+            let caller = self.env().caller();
+            self.deposit_to(pid, amount, caller, caller);
+        }
+
+        // deposit on behalf of `user`, the tokens are paid by the caller.
+        #[ink(message)]
+        pub fn deposit_for(&mut self, pid: u32, amount: Balance, user: AccountId) {
+            assert_ne!(user, Default::default(), "user is zero");
+            let caller = self.env().caller();
+            self.deposit_to(pid, amount, caller, user);
+        }
+
+        fn deposit_to(&mut self, pid: u32, amount: Balance, payer: AccountId, user: AccountId) {
             assert!(self.can_deposit, "can not");
             assert!(pid < self.pool_info.len() as u32, "invalid _pid");
-            let caller = self.env().caller();
-            let self_account = self.env().account_id();
 
             let pool = self.pool_info.get(pid as usize).unwrap();
+            let info = self.user_info_of(pid, user);
+            if let Some(max) = self.user_capacity_max.get(&pid) {
+                assert!(info.amount + amount <= *max, "exceed user limit");
+            }
+            assert!(
+                pool.amount + amount <= *self.capacity_max.get(&pid).unwrap(),
+                "exceed the total limit"
+            );
+            self.update_pool(pid);
 
-                        // let user = self.user_info.get(&(pid, caller)).unwrap();
+            let lp_token = self.pool_info.get(pid as usize).unwrap().lp_token;
+            assert!(self.token_transfer_from(lp_token, payer, amount));
 
-                        // assert!(
-                        //     user.amount + amount <= *self.user_capacity_max.get(&pid).unwrap(),
-                        //     "exceed user limit"
-                        // );
-                        assert!(
-                            pool.amount + amount <= *self.capacity_max.get(&pid).unwrap(),
-                            "exceed the total limit"
-                        );
-            self.update_pool(pid);
+            let pending = self.settle_position(pid, user, info.amount + amount);
+            if pending > 0 {
+                self.safe_nsure_transfer(user, pending);
+            }
 
-            let pool = self.pool_info.get_mut(pid as usize).unwrap();
-                         ///// let user = self.user_info.get_mut(&(pid, caller)).unwrap();
+            self.env().emit_event(EDeposit {
+                user,
+                pid,
+                amount,
+            });
+        }
 
-            let mut amount_info = self.amount.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
-            let mut reward_debt = self.reward_debt.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
+        // unstake, the tokens can be withdrawn once `pending_duration` has passed.
+        // needs the 65 bytes ecdsa `signature` of `signer` over
+        // (contract, pid, caller, amount, nonce, deadline), see `unstake_hash`.
+        #[ink(message)]
+        pub fn unstake(&mut self, pid: u32, amount: Balance, deadline: u64, signature: Vec<u8>) {
+            assert!(pid < self.pool_info.len() as u32, "invalid _pid");
+            assert!(amount > 0, "invalid amount");
+            let caller = self.env().caller();
+            self.verify_signer(pid, caller, amount, deadline, &signature);
+            self.unbond(pid, caller, amount);
+        }
 
-///todo let amount_info = self.amount.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
-            let mut lp_token: Erc20 = FromAccountId::from_account_id(pool.lp_token);
-            assert!(lp_token.transfer_from(caller, self_account, amount).is_ok());
+        // moves `amount` of the position of `user` in pool `pid` to a new unbond request.
+        fn unbond(&mut self, pid: u32, user: AccountId, amount: Balance) {
+            let info = self.user_info_of(pid, user);
+            let locked = self.active_lock(pid, user).map(|lock| lock.amount).unwrap_or(0);
+            assert!(info.amount - locked >= amount, "unstake: insufficient assets");
+            let mut requests = self.unbond_requests_of(pid, user);
+            assert!(requests.len() < MAX_UNBOND_REQUESTS, "too many unbond requests");
 
-            let pending = amount_info * (pool.acc_nsure_per_share) /  10u128.saturating_pow(12)   - reward_debt;
+            self.update_pool(pid);
+            let pending = self.settle_position(pid, user, info.amount - amount);
 
-            amount_info = amount_info + amount;
-            reward_debt = amount_info * pool.acc_nsure_per_share / 10u128.saturating_pow(12);
-            pool.amount = pool.amount + amount;
-            
-            self.amount.insert(caller, amount_info);
+            let timestamp = Self::env().block_timestamp();
+            requests.push(UnbondRequest {
+                amount,
+                unlock_at: timestamp + self.pending_duration,
+            });
+            self.unbond_requests.insert((pid, user), requests);
+
+            let mut info = self.user_info_of(pid, user);
+            info.pending_at = timestamp;
+            info.pending_withdrawal = info.pending_withdrawal + amount;
+            self.user_info.insert((pid, user), info);
+
+            let pool = self.pool_info.get_mut(pid as usize).unwrap();
+            pool.pending = pool.pending + amount;
 
             if pending > 0 {
-                self.safe_nsure_transfer(caller, pending);
+                self.safe_nsure_transfer(user, pending);
             }
 
-            self.env().emit_event(EDeposit {
-                user: caller,
+            self.env().emit_event(Unstake {
+                user,
                 pid,
                 amount,
             });
         }
 
-        // unstake, need pending sometime
+        // deposit `amount` locked for `lock_blocks`, adding to the caller's lock in pool `pid`.
+        // the whole lock then unlocks at the later of both ends, boosted for the time left.
         #[ink(message)]
-        pub fn unstake(&mut self, pid: u32, amount: Balance) {
-            // TODO verify signer
+        pub fn deposit_locked(&mut self, pid: u32, amount: Balance, lock_blocks: BlockNumber) {
+            assert!(lock_blocks > 0, "invalid lock");
+            assert!(lock_blocks <= self.max_lock_blocks(), "lock too long");
+            let caller = self.env().caller();
+            let block_number = self.env().block_number();
+
+            let (locked, unlock_at) = match self.active_lock(pid, caller) {
+                Some(lock) => (lock.amount, lock.unlock_at.max(block_number + lock_blocks)),
+                None => (0, block_number + lock_blocks),
+            };
+            // the lock is in place before the position is settled, so its weight is boosted
+            self.locks.insert(
+                (pid, caller),
+                StakeLock {
+                    amount: locked + amount,
+                    unlock_at,
+                    boost: self.boost_for(unlock_at - block_number),
+                },
+            );
+            self.deposit_to(pid, amount, caller, caller);
 
+            self.env().emit_event(DepositLocked {
+                user: caller,
+                pid,
+                amount,
+                unlock_at,
+            });
+        }
+
+        // unstake the whole lock of the caller in pool `pid` before it expires,
+        // `early_exit_penalty` of it is sent to the treasury and the rest unbonds as usual.
+        // the signature is over the locked amount, see `unstake`.
+        #[ink(message)]
+        pub fn unstake_locked_early(&mut self, pid: u32, deadline: u64, signature: Vec<u8>) {
             assert!(pid < self.pool_info.len() as u32, "invalid _pid");
             let caller = self.env().caller();
-            // let user = self.user_info.get(&(pid, caller)).unwrap();
-            // assert!(user.amount >= amount, "unstake: insufficient assets");
+            let lock = self.active_lock(pid, caller).expect("no active lock");
+            self.verify_signer(pid, caller, lock.amount, deadline, &signature);
+            self.locks.take(&(pid, caller));
 
+            let penalty = lock.amount * self.early_exit_penalty / BPS_DENOMINATOR;
+            if penalty > 0 {
+                self.update_pool(pid);
+                let amount = self.user_info_of(pid, caller).amount;
+                let pending = self.settle_position(pid, caller, amount - penalty);
+
+                let lp_token = self.pool_info.get(pid as usize).unwrap().lp_token;
+                assert!(self.token_transfer(lp_token, self.treasury, penalty));
+                if pending > 0 {
+                    self.safe_nsure_transfer(caller, pending);
+                }
+            }
+            self.unbond(pid, caller, lock.amount - penalty);
 
-            // let mut amount_info = self.amount.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
-            // let mut reward_debt = self.reward_debt.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
-            // let pending_at_info = self.pending_at.get_mut(&caller).map(|i|*i).unwrap_or(0u64);
-            // let mut pending_withdrawal_info = self.pending_withdrawal.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
+            self.env().emit_event(EarlyExit {
+                user: caller,
+                pid,
+                amount: lock.amount,
+                penalty,
+            });
+        }
 
+        // drops the boost of the expired lock of `user` in pool `pid`, anyone can call it.
+        #[ink(message)]
+        pub fn kick(&mut self, pid: u32, user: AccountId) {
+            assert!(pid < self.pool_info.len() as u32, "invalid _pid");
+            assert!(self.locks.contains_key(&(pid, user)), "no lock");
+            assert!(self.active_lock(pid, user).is_none(), "still locked");
+            self.locks.take(&(pid, user));
 
             self.update_pool(pid);
-            let pool = self.pool_info.get_mut(pid as usize).unwrap();
-            // let user = self.user_info.get_mut(&(pid, caller)).unwrap();
+            let amount = self.user_info_of(pid, user).amount;
+            let pending = self.settle_position(pid, user, amount);
+            if pending > 0 {
+                self.safe_nsure_transfer(user, pending);
+            }
+        }
 
-            let mut amount_info = self.amount.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
-            let mut reward_debt = self.reward_debt.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
-            let mut pending_at_info = self.pending_at.get_mut(&caller).map(|i|*i).unwrap_or(0u64);
-            let mut pending_withdrawal_info = self.pending_withdrawal.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
+        #[ink(message)]
+        pub fn lock_of(&self, pid: u32, user: AccountId) -> Option<StakeLock> {
+            self.locks.get(&(pid, user)).copied()
+        }
 
+        #[ink(message)]
+        pub fn weight_of_user(&self, pid: u32, user: AccountId) -> Balance {
+            self.user_info_of(pid, user).weight
+        }
 
-            let pending = amount_info * pool.acc_nsure_per_share / 10u128.saturating_pow(12) - reward_debt;
+        #[ink(message)]
+        pub fn boost_curve(&self) -> Vec<BoostPoint> {
+            self.boost_curve.clone()
+        }
 
-            amount_info = amount_info - amount;
-            reward_debt = amount_info * pool.acc_nsure_per_share / 10u128.saturating_pow(12);
+        #[ink(message)]
+        pub fn max_lock_blocks(&self) -> BlockNumber {
+            self.boost_curve.last().unwrap().lock_blocks
+        }
 
-            pending_at_info = Self::env().block_timestamp();
-            pending_withdrawal_info = pending_withdrawal_info + amount;
+        // Returns the boost in basis points of a lock of `lock_blocks`.
+        #[ink(message)]
+        pub fn boost_for(&self, lock_blocks: BlockNumber) -> u128 {
+            for points in self.boost_curve.windows(2) {
+                let (from, to) = (points[0], points[1]);
+                if lock_blocks <= to.lock_blocks {
+                    return from.boost
+                        + (to.boost - from.boost) * (lock_blocks - from.lock_blocks) as u128
+                            / (to.lock_blocks - from.lock_blocks) as u128;
+                }
+            }
+            self.boost_curve.last().unwrap().boost
+        }
 
-            pool.pending = pool.pending + amount;
+        // Sets the boost curve, existing locks keep their boost.
+        // The curve starts at lock 0 with 1x, and rises with the lock up to `MAX_BOOST`.
+        #[ink(message)]
+        pub fn set_boost_curve(&mut self, curve: Vec<BoostPoint>) {
+            self.only_owner();
+            assert!(curve.len() >= 2, "invalid curve");
+            assert!(
+                curve[0].lock_blocks == 0 && curve[0].boost == BPS_DENOMINATOR,
+                "invalid curve"
+            );
+            for points in curve.windows(2) {
+                assert!(
+                    points[0].lock_blocks < points[1].lock_blocks
+                        && points[0].boost <= points[1].boost,
+                    "invalid curve"
+                );
+            }
+            let last = *curve.last().unwrap();
+            assert!(last.boost <= MAX_BOOST, "boost too high");
+            self.boost_curve = curve;
+            self.env().emit_event(SetBoostCurve {
+                max_lock_blocks: last.lock_blocks,
+                max_boost: last.boost,
+            });
+        }
 
-            self.safe_nsure_transfer(caller, pending);
+        #[ink(message)]
+        pub fn set_early_exit_penalty(&mut self, penalty: u128) {
+            self.only_owner();
+            assert!(penalty <= BPS_DENOMINATOR, "invalid penalty");
+            self.early_exit_penalty = penalty;
+            self.env().emit_event(SetEarlyExitPenalty { penalty });
+        }
 
-            self.env().emit_event(Unstake {
+        #[ink(message)]
+        pub fn set_treasury(&mut self, treasury: AccountId) {
+            self.only_owner();
+            assert!(treasury != Default::default(), "treasury is zero");
+            self.treasury = treasury;
+            self.env().emit_event(SetTreasury { treasury });
+        }
+
+        // withdraw the unstaked tokens whose pending period is over.
+        // when it's pending while a claim occurs, the value of the withdrawal will decrease as usual
+        // so we keep the claim function by this tool.
+        #[ink(message)]
+        pub fn withdraw(&mut self, pid: u32) -> Balance {
+            assert!(pid < self.pool_info.len() as u32, "invalid _pid");
+            let caller = self.env().caller();
+            let amount = self.release_unbonded(pid, caller);
+            assert!(amount > 0, "still pending");
+
+            let lp_token = self.pool_info.get(pid as usize).unwrap().lp_token;
+            assert!(self.token_transfer(lp_token, caller, amount));
+            self.env().emit_event(Withdraw {
                 user: caller,
                 pid,
                 amount,
             });
+            amount
         }
 
-        // when it's pending while a claim occurs, the value of the withdrawal will decrease as usual
-        // so we keep the claim function by this tool.
+        // withdraw the unstaked tokens of `user` whose pending period is over to the zapper,
+        // for its zap out.
         #[ink(message)]
-        pub fn withdraw(&mut self, pid: u32) {
+        pub fn withdraw_for(&mut self, pid: u32, user: AccountId) -> Balance {
+            assert!(self.env().caller() == self.zapper, "not zapper");
             assert!(pid < self.pool_info.len() as u32, "invalid _pid");
             let caller = self.env().caller();
-            let timestamp = self.env().block_timestamp();
-            let pool: &mut PoolInfo = self.pool_info.get_mut(pid as usize).unwrap();
-            // let user = self.user_info.get_mut(&(pid, caller)).unwrap();
+            let amount = self.release_unbonded(pid, user);
+            assert!(amount > 0, "still pending");
 
-            let mut amount_info = self.amount.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
-            let mut reward_debt = self.reward_debt.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
-            let pending_at_info = self.pending_at.get_mut(&caller).map(|i|*i).unwrap_or(0u64);
-            let mut pending_withdrawal_info = self.pending_withdrawal.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
+            let lp_token = self.pool_info.get(pid as usize).unwrap().lp_token;
+            assert!(self.token_transfer(lp_token, caller, amount));
 
-            // assert!(
-            //     timestamp >= pending_at_info + self.pending_duration,
-            //     "still pending"
-            // );
+            self.env().emit_event(Withdraw {
+                user,
+                pid,
+                amount,
+            });
+            amount
+        }
 
-            // let amount = pending_withdrawal_info;
-            let amount = amount_info;
-            pool.amount = pool.amount - amount;
-            pool.pending = pool.pending - amount;
+        // withdraw the staked tokens at once without caring about rewards, which are forfeited.
+        // EMERGENCY ONLY, when rewards can't be paid and deposit, unstake and claim revert,
+        // so only once the owner calls set_emergency.
+        // the whole position is returned, locks are dropped without penalty as the emergency
+        // isn't the user's choice. unstaked tokens are withdrawn as usual.
+        #[ink(message)]
+        pub fn emergency_withdraw(&mut self, pid: u32) -> Balance {
+            assert!(self.emergency, "not in emergency");
+            assert!(pid < self.pool_info.len() as u32, "invalid _pid");
+            let caller = self.env().caller();
+            self.locks.take(&(pid, caller));
+            let amount = self.forfeit_position(pid, caller);
+            assert!(amount > 0, "nothing staked");
 
-            pending_withdrawal_info = 0;
+            let lp_token = self.pool_info.get(pid as usize).unwrap().lp_token;
+            assert!(self.token_transfer(lp_token, caller, amount));
 
-            let mut lp_token: Erc20 = FromAccountId::from_account_id(pool.lp_token);
-            assert!(lp_token.transfer(caller, amount).is_ok());
-            self.amount.insert(caller, 0);
-            self.env().emit_event(Withdraw {
+            self.env().emit_event(EmergencyWithdraw {
                 user: caller,
                 pid,
                 amount,
             });
+            amount
+        }
+
+        #[ink(message)]
+        pub fn set_zapper(&mut self, zapper: AccountId) {
+            self.only_owner();
+            assert!(zapper != Default::default(), "zapper is zero");
+            self.zapper = zapper;
+            self.env().emit_event(SetZapper { zapper });
         }
 
         //claim reward
@@ -576,17 +1329,8 @@ mod capital_stake {
 
             self.update_pool(pid);
 
-            let pool = self.pool_info.get(pid as usize).unwrap();
-            // let user = self.user_info.get_mut(&(pid, caller)).unwrap();
-
-            let mut amount_info = self.amount.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
-            let mut reward_debt = self.reward_debt.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
-            let pending_at_info = self.pending_at.get_mut(&caller).map(|i|*i).unwrap_or(0u64);
-            let mut pending_withdrawal_info = self.pending_withdrawal.get_mut(&caller).map(|i|*i).unwrap_or(0u128);
-
-
-            reward_debt = amount_info * pool.acc_nsure_per_share / 10u128.saturating_pow(12);
-            let pending = amount_info * pool.acc_nsure_per_share / 10u128.saturating_pow(12) - reward_debt;
+            let amount = self.user_info_of(pid, caller).amount;
+            let pending = self.settle_position(pid, caller, amount);
             self.safe_nsure_transfer(caller, pending);
 
             self.env().emit_event(Claim {
@@ -596,20 +1340,359 @@ mod capital_stake {
             });
         }
 
+        // claim the rewards of every pool the caller has a position in, with one NSURE transfer.
+        #[ink(message)]
+        pub fn claim_all(&mut self) -> Balance {
+            let pids: Vec<u32> = (0..self.pool_info.len() as u32).collect();
+            self.claim_many(pids)
+        }
+
+        // claim the rewards of pools `pids`, each at most once, with one NSURE transfer
+        // for the sum and one for each extra reward token.
+        // a Claim event is emitted for each pool the caller has a position in.
+        #[ink(message)]
+        pub fn claim_many(&mut self, pids: Vec<u32>) -> Balance {
+            assert!(pids.len() <= self.pool_info.len(), "too many pools");
+            let mut sorted = pids.clone();
+            sorted.sort_unstable();
+            assert!(sorted.windows(2).all(|pair| pair[0] != pair[1]), "duplicate pid");
+            let caller = self.env().caller();
+            let mut total: Balance = 0;
+            let mut extras = Vec::new();
+            for &pid in pids.iter() {
+                assert!(pid < self.pool_info.len() as u32, "invalid _pid");
+                let amount = self.user_info_of(pid, caller).amount;
+                if amount == 0 {
+                    continue;
+                }
+
+                self.update_pool(pid);
+                let pending = self.settle_rewards(pid, caller, amount, &mut extras);
+                total = total + pending;
+
+                self.env().emit_event(Claim {
+                    user: caller,
+                    pid,
+                    amount: pending,
+                });
+            }
+            if total > 0 {
+                self.safe_nsure_transfer(caller, total);
+            }
+            self.pay_extra_rewards(caller, extras);
+            total
+        }
+
         pub fn is_pending(&self, pid: u32) -> (bool, u64) {
             let caller = self.env().caller();
-            // let user = self.user_info.get(&(pid, caller)).unwrap();
-            let  amount_info = self.amount.get(&caller).map(|i|*i).unwrap_or(0u128);
-            let  reward_debt = self.reward_debt.get(&caller).map(|i|*i).unwrap_or(0u128);
-            let pending_at_info = self.pending_at.get(&caller).map(|i|*i).unwrap_or(0u64);
-            let  pending_withdrawal_info = self.pending_withdrawal.get(&caller).map(|i|*i).unwrap_or(0u128);
+            let info = self.user_info_of(pid, caller);
 
             let timestamp = self.env().block_timestamp();
-            if timestamp >= pending_at_info + self.pending_duration {
+            if timestamp >= info.pending_at + self.pending_duration {
                 return (false, 0);
             }
 
-            return (true, pending_at_info + self.pending_duration - timestamp);
+            return (true, info.pending_at + self.pending_duration - timestamp);
+        }
+
+        /// Returns the unbond requests of `user` in pool `pid` waiting for withdrawal.
+        #[ink(message)]
+        pub fn unbond_requests_of(&self, pid: u32, user: AccountId) -> Vec<UnbondRequest> {
+            self.unbond_requests
+                .get(&(pid, user))
+                .cloned()
+                .unwrap_or_default()
+        }
+
+        // Removes the matured unbond requests of `user` in pool `pid` and returns
+        // their total, which the caller has to transfer.
+        fn release_unbonded(&mut self, pid: u32, user: AccountId) -> Balance {
+            let timestamp = self.env().block_timestamp();
+            let (matured, requests): (Vec<UnbondRequest>, Vec<UnbondRequest>) = self
+                .unbond_requests_of(pid, user)
+                .into_iter()
+                .partition(|request| request.unlock_at <= timestamp);
+            let amount: Balance = matured.iter().map(|request| request.amount).sum();
+            if amount == 0 {
+                return 0;
+            }
+            self.unbond_requests.insert((pid, user), requests);
+
+            let mut info = self.user_info_of(pid, user);
+            info.pending_withdrawal = info.pending_withdrawal - amount;
+            self.user_info.insert((pid, user), info);
+
+            let pool = self.pool_info.get_mut(pid as usize).unwrap();
+            pool.pending = pool.pending - amount;
+            amount
+        }
+
+        /// Returns the nonce the next unstake signature of `account` has to be made with.
+        #[ink(message)]
+        pub fn nonce_of(&self, account: AccountId) -> u128 {
+            self.nonces.get(&account).copied().unwrap_or(0)
+        }
+
+        /// Returns the hash `signer` signs to let `account` unstake `amount` from pool `pid`
+        /// until `deadline`, with the current nonce of `account`.
+        #[ink(message)]
+        pub fn unstake_hash(&self, pid: u32, account: AccountId, amount: Balance, deadline: u64) -> [u8; 32] {
+            let mut output = <Blake2x256 as HashOutput>::Type::default();
+            ink_env::hash_encoded::<Blake2x256, _>(
+                &(
+                    self.env().account_id(),
+                    pid,
+                    account,
+                    amount,
+                    self.nonce_of(account),
+                    deadline,
+                ),
+                &mut output,
+            );
+            output
+        }
+
+        // Checks `signature` of the unstake by the signer and consumes the nonce of `account`.
+        fn verify_signer(
+            &mut self,
+            pid: u32,
+            account: AccountId,
+            amount: Balance,
+            deadline: u64,
+            signature: &[u8],
+        ) {
+            assert!(self.env().block_timestamp() <= deadline, "signature expired");
+            assert_eq!(signature.len(), 65, "invalid signature");
+
+            let mut sig = [0u8; 65];
+            sig.copy_from_slice(signature);
+            let hash = self.unstake_hash(pid, account, amount, deadline);
+            let mut public_key = [0u8; 33];
+            assert!(
+                ink_env::ecdsa_recover(&sig, &hash, &mut public_key).is_ok(),
+                "invalid signature"
+            );
+
+            // ecdsa accounts are the blake2 hash of the compressed public key
+            let mut signer = <Blake2x256 as HashOutput>::Type::default();
+            ink_env::hash_bytes::<Blake2x256>(&public_key, &mut signer);
+            assert!(AccountId::from(signer) == self.signer, "invalid signer");
+
+            let nonce = self.nonce_of(account);
+            self.nonces.insert(account, nonce + 1);
+        }
+
+        fn user_info_of(&self, pid: u32, user: AccountId) -> UserInfo {
+            self.user_info.get(&(pid, user)).copied().unwrap_or_default()
+        }
+
+        // Sets the staked amount of `user` in pool `pid` to `amount` and returns
+        // the reward pending up to now, which the caller has to pay out.
+        // The extra rewards are paid out here.
+        fn settle_position(&mut self, pid: u32, user: AccountId, amount: Balance) -> Balance {
+            let mut extras = Vec::new();
+            let pending = self.settle_rewards(pid, user, amount, &mut extras);
+            self.pay_extra_rewards(user, extras);
+            pending
+        }
+
+        // `settle_position` leaving the extra rewards pending in `extras`, as
+        // (pid, token, amount), for `pay_extra_rewards`.
+        fn settle_rewards(
+            &mut self,
+            pid: u32,
+            user: AccountId,
+            amount: Balance,
+            extras: &mut Vec<(u32, AccountId, Balance)>,
+        ) -> Balance {
+            let weight = self.weight_of(pid, user, amount);
+            let pool = self.pool_info.get_mut(pid as usize).unwrap();
+            let mut info = self.user_info.get(&(pid, user)).copied().unwrap_or_default();
+
+            let pending = info.weight * pool.acc_nsure_per_share / 10u128.saturating_pow(12) - info.reward_debt;
+            let old_weight = info.weight;
+            pool.amount = pool.amount + amount - info.amount;
+            pool.total_weight = pool.total_weight + weight - info.weight;
+            info.amount = amount;
+            info.weight = weight;
+            info.reward_debt = weight * pool.acc_nsure_per_share / 10u128.saturating_pow(12);
+            self.user_info.insert((pid, user), info);
+            self.settle_extra_rewards(pid, user, old_weight, weight, extras);
+            pending
+        }
+
+        // Zeroes the staked amount of `user` in pool `pid` without updating the pool and
+        // returns it. The NSURE reward already credited to the position goes back to the
+        // reward reserve, the extra rewards stay with their pool.
+        fn forfeit_position(&mut self, pid: u32, user: AccountId) -> Balance {
+            let pool = self.pool_info.get_mut(pid as usize).unwrap();
+            let mut info = self.user_info.get(&(pid, user)).copied().unwrap_or_default();
+
+            let forfeited = (info.weight * pool.acc_nsure_per_share / 10u128.saturating_pow(12))
+                .saturating_sub(info.reward_debt)
+                .min(self.reward_debt_total);
+            self.reward_debt_total = self.reward_debt_total - forfeited;
+            self.reward_reserve = self.reward_reserve + forfeited;
+
+            let amount = info.amount;
+            pool.amount = pool.amount - info.amount;
+            pool.total_weight = pool.total_weight - info.weight;
+            info.amount = 0;
+            info.weight = 0;
+            info.reward_debt = 0;
+            self.user_info.insert((pid, user), info);
+
+            for extra in pool.extra_rewards.iter() {
+                self.extra_reward_debt.take(&(pid, extra.token, user));
+            }
+            amount
+        }
+
+        // Returns the weight of a position of `amount` of `user` in pool `pid`,
+        // with the lock boost while the lock lasts.
+        fn weight_of(&self, pid: u32, user: AccountId, amount: Balance) -> Balance {
+            match self.active_lock(pid, user) {
+                Some(lock) => amount - lock.amount + lock.amount * lock.boost / BPS_DENOMINATOR,
+                None => amount,
+            }
+        }
+
+        fn active_lock(&self, pid: u32, user: AccountId) -> Option<StakeLock> {
+            let block_number = self.env().block_number();
+            self.locks
+                .get(&(pid, user))
+                .copied()
+                .filter(|lock| lock.unlock_at > block_number)
+        }
+
+        // Adds the extra rewards of `user` in pool `pid` pending for `old_weight`, with
+        // what a failed transfer left owed, to `extras` and resets their reward debts
+        // to the new `weight`.
+        fn settle_extra_rewards(
+            &mut self,
+            pid: u32,
+            user: AccountId,
+            old_weight: Balance,
+            weight: Balance,
+            extras: &mut Vec<(u32, AccountId, Balance)>,
+        ) {
+            let extra_rewards = self.pool_info.get(pid as usize).unwrap().extra_rewards.clone();
+            for extra in extra_rewards.iter() {
+                let key = (pid, extra.token, user);
+                let debt = self.extra_reward_debt.get(&key).copied().unwrap_or(0);
+                let pending = old_weight * extra.acc_per_share / 10u128.saturating_pow(12) - debt
+                    + self.extra_reward_owed.take(&key).unwrap_or(0);
+                self.extra_reward_debt
+                    .insert(key, weight * extra.acc_per_share / 10u128.saturating_pow(12));
+                if pending > 0 {
+                    extras.push((pid, extra.token, pending));
+                }
+            }
+        }
+
+        // Pays the extra rewards in `extras` to `user` with one transfer per token.
+        // A reward token that fails to transfer doesn't block the pools, what it owes
+        // is kept for the next settlement.
+        fn pay_extra_rewards(&mut self, user: AccountId, extras: Vec<(u32, AccountId, Balance)>) {
+            let mut tokens: Vec<AccountId> = extras.iter().map(|&(_, token, _)| token).collect();
+            tokens.sort_unstable();
+            tokens.dedup();
+            for token in tokens {
+                let total: Balance = extras
+                    .iter()
+                    .filter(|extra| extra.1 == token)
+                    .map(|extra| extra.2)
+                    .sum();
+                let paid = self.try_transfer(token, user, total);
+                for &(pid, _, amount) in extras.iter().filter(|extra| extra.1 == token) {
+                    if paid {
+                        self.env().emit_event(ClaimExtraReward {
+                            user,
+                            pid,
+                            token,
+                            amount,
+                        });
+                    } else {
+                        self.extra_reward_owed.insert((pid, token, user), amount);
+                    }
+                }
+            }
+        }
+
+        // The token calls go through the helpers below, the unit tests swap them for the
+        // `mock` module as they can't reach the token contracts off-chain.
+
+        // Transfers `amount` of the erc20 `token` to `to`, returns false instead of
+        // reverting if the transfer fails or the token contract traps.
+        #[cfg(not(test))]
+        fn try_transfer(&self, token: AccountId, to: AccountId, amount: Balance) -> bool {
+            build_call::<DefaultEnvironment>()
+                .callee(token)
+                .gas_limit(0)
+                .transferred_value(0)
+                .exec_input(
+                    ExecutionInput::new(Selector::new(TRANSFER_SELECTOR))
+                        .push_arg(to)
+                        .push_arg(amount),
+                )
+                .returns::<ReturnType<Result<(), erc20::Error>>>()
+                .fire()
+                .map(|result| result.is_ok())
+                .unwrap_or(false)
+        }
+
+        #[cfg(not(test))]
+        fn token_transfer(&mut self, token: AccountId, to: AccountId, amount: Balance) -> bool {
+            let mut token: Erc20 = FromAccountId::from_account_id(token);
+            token.transfer(to, amount).is_ok()
+        }
+
+        // pulls `amount` of the erc20 `token` of `from` into the contract
+        #[cfg(not(test))]
+        fn token_transfer_from(&mut self, token: AccountId, from: AccountId, amount: Balance) -> bool {
+            let self_account = self.env().account_id();
+            let mut token: Erc20 = FromAccountId::from_account_id(token);
+            token.transfer_from(from, self_account, amount).is_ok()
+        }
+
+        #[cfg(not(test))]
+        fn mint_nsure(&mut self, amount: Balance) -> bool {
+            let self_account = self.env().account_id();
+            self.nsure.mint(self_account, amount).is_ok()
+        }
+
+        #[cfg(test)]
+        fn try_transfer(&self, token: AccountId, to: AccountId, amount: Balance) -> bool {
+            mock::transfer(token, self.env().account_id(), to, amount)
+        }
+
+        #[cfg(test)]
+        fn token_transfer(&mut self, token: AccountId, to: AccountId, amount: Balance) -> bool {
+            mock::transfer(token, self.env().account_id(), to, amount)
+        }
+
+        #[cfg(test)]
+        fn token_transfer_from(&mut self, token: AccountId, from: AccountId, amount: Balance) -> bool {
+            mock::transfer(token, from, self.env().account_id(), amount)
+        }
+
+        #[cfg(test)]
+        fn mint_nsure(&mut self, amount: Balance) -> bool {
+            mock::mint(self.nsure_token, self.env().account_id(), amount)
+        }
+
+        // credits the reward of `extra` since its last update to the `lp_supply` staked
+        fn accrue_extra_reward(extra: &mut ExtraReward, lp_supply: Balance, block_number: BlockNumber) {
+            if block_number <= extra.last_reward_block {
+                return;
+            }
+            if lp_supply != 0 {
+                let blocks = (block_number - extra.last_reward_block) as u128;
+                let reward = (extra.rate * blocks).min(extra.reserve);
+                extra.acc_per_share = extra.acc_per_share + reward * 10u128.saturating_pow(12) / lp_supply;
+                extra.reserve = extra.reserve - reward;
+            }
+            extra.last_reward_block = block_number;
         }
 
         fn mass_update_pools(&mut self) {
@@ -627,57 +1710,123 @@ mod capital_stake {
 
         fn update_pool(&mut self, pid: u32) {
             assert!(pid < self.pool_info.len() as u32, "invalid _pid");
-            let self_account = self.env().account_id();
             let block_number = self.env().block_number();
             let pool: &mut PoolInfo = self.pool_info.get_mut(pid as usize).unwrap();
+            let lp_supply = pool.total_weight;
+            for extra in pool.extra_rewards.iter_mut() {
+                Self::accrue_extra_reward(extra, lp_supply, block_number);
+            }
+
+            let pool: &PoolInfo = self.pool_info.get(pid as usize).unwrap();
             if block_number <= pool.last_reward_block {
                 return;
             }
 
-            let lp_token: Erc20 = FromAccountId::from_account_id(pool.lp_token);
-            let lp_supply = lp_token.balance_of(self_account);
-            if lp_supply == 0 {
-                pool.last_reward_block = block_number;
-                return;
+            // unstaked tokens waiting for withdrawal earn nothing
+            let lp_supply = pool.total_weight;
+            let mut nsure_reward = 0;
+            if lp_supply != 0 {
+                let multiplier = self.get_multiplier(pool.last_reward_block, block_number);
+                nsure_reward = multiplier * pool.alloc_point / self.total_alloc_point;
             }
 
-            let multiplier = Self::get_multiplier(pool.last_reward_block, block_number);
-            let nsure_reward = multiplier as u128 * self.nsure_per_block * pool.alloc_point
-                / self.total_alloc_point;
+            // the pool is credited with what the reward source actually provided
+            let nsure_reward = match self.obtain_reward(nsure_reward) {
+                Some(reward) => reward,
+                None => return,
+            };
 
-                //test
-                    let rewardtest :Balance = 40000000000;
-                //test
-            // if self.nsure.mint(self_account, nsure_reward).is_ok() {
-                if self.nsure.mint(self_account, rewardtest).is_ok() {
+            let pool: &mut PoolInfo = self.pool_info.get_mut(pid as usize).unwrap();
+            if lp_supply != 0 {
                 pool.acc_nsure_per_share =
                     pool.acc_nsure_per_share + (nsure_reward * 10u128.saturating_pow(12) / lp_supply);
-                pool.last_reward_block = block_number;
             }
+            pool.last_reward_block = block_number;
         }
 
-        #[ink(message)]
-        pub fn  show_mint(&mut self,user:AccountId ) {
-            self.nsure.mint(user,3);
+        // Obtains up to `amount` NSURE from the reward source and returns what was
+        // obtained, or `None` if minting failed.
+        fn obtain_reward(&mut self, amount: Balance) -> Option<Balance> {
+            let amount = amount.min(self.available_reward());
+            if amount == 0 {
+                return Some(0);
+            }
+            match self.reward_source {
+                RewardSource::Mint => {
+                    if !self.mint_nsure(amount) {
+                        return None;
+                    }
+                    self.minted = self.minted + amount;
+                }
+                RewardSource::Reserve => {
+                    self.reward_reserve = self.reward_reserve - amount;
+                }
+            }
+            self.reward_debt_total = self.reward_debt_total + amount;
+            Some(amount)
+        }
+
+        fn available_reward(&self) -> Balance {
+            match self.reward_source {
+                RewardSource::Mint => self.mint_cap.saturating_sub(self.minted),
+                RewardSource::Reserve => self.reward_reserve,
+            }
         }
 
         // #[ink(message)]
         // pub fn show_nsure(&self) -> Lazy<Erc20>{
         //     self.nsure
         // }
-        fn get_multiplier(from: BlockNumber, to: BlockNumber) -> u128 {
-            (to - from) as u128
+        // Returns the NSURE emitted over the blocks from `from` to `to`, across the
+        // emission segments, halvings and the end block.
+        fn get_multiplier(&self, from: BlockNumber, to: BlockNumber) -> u128 {
+            let to = to.min(self.end_block);
+            let mut reward = 0;
+            for (i, segment) in self.emission_schedule.iter().enumerate() {
+                let segment_end = self
+                    .emission_schedule
+                    .get(i + 1)
+                    .map(|next| next.start_block)
+                    .unwrap_or(BlockNumber::MAX);
+                let start = from.max(segment.start_block);
+                let end = to.min(segment_end);
+                if start < end {
+                    reward += self.segment_emission(segment, start, end);
+                }
+            }
+            reward
         }
 
+        fn segment_emission(&self, segment: &EmissionSegment, from: BlockNumber, to: BlockNumber) -> u128 {
+            if self.halving_interval == 0 {
+                return segment.rate * (to - from) as u128;
+            }
+            let mut reward = 0;
+            let mut block = from;
+            while block < to {
+                let halvings = (block - segment.start_block) / self.halving_interval;
+                if halvings >= 128 {
+                    break;
+                }
+                let next = segment
+                    .start_block
+                    .saturating_add((halvings + 1).saturating_mul(self.halving_interval));
+                let end = to.min(next);
+                reward += (segment.rate >> halvings) * (end - block) as u128;
+                block = end;
+            }
+            reward
+        }
+
+        // pays out of the rewards owed only, never out of the reserve or staked tokens
         fn safe_nsure_transfer(&mut self, to: AccountId, amount: Balance) {
             assert_ne!(to, Default::default(), "to is zero");
-            let self_account = self.env().account_id();
-            let nsure_bal = self.nsure.balance_of(self_account);
-            if amount > nsure_bal {
-                assert!(self.nsure.transfer(to, nsure_bal).is_ok());
-            } else {
-                assert!(self.nsure.transfer(to, amount).is_ok());
+            let amount = amount.min(self.reward_debt_total);
+            if amount == 0 {
+                return;
             }
+            assert!(self.token_transfer(self.nsure_token, to, amount));
+            self.reward_debt_total = self.reward_debt_total - amount;
         }
 
         #[ink(message)]
@@ -702,6 +1851,65 @@ mod capital_stake {
         }
     }
 
+    /// Stands in for the lp, NSURE and extra reward tokens in the unit tests.
+    #[cfg(test)]
+    mod mock {
+        use super::{AccountId, Balance};
+        use std::cell::RefCell;
+        use std::collections::{BTreeMap, BTreeSet};
+
+        thread_local! {
+            // balances by token and owner
+            static BALANCES: RefCell<BTreeMap<(AccountId, AccountId), Balance>> =
+                RefCell::new(BTreeMap::new());
+            // successful transfers by token
+            static TRANSFERS: RefCell<BTreeMap<AccountId, u32>> = RefCell::new(BTreeMap::new());
+            // tokens whose transfers fail
+            static FAILING: RefCell<BTreeSet<AccountId>> = RefCell::new(BTreeSet::new());
+        }
+
+        pub fn balance_of(token: AccountId, owner: AccountId) -> Balance {
+            BALANCES.with(|b| b.borrow().get(&(token, owner)).copied().unwrap_or(0))
+        }
+
+        pub fn set_balance(token: AccountId, owner: AccountId, amount: Balance) {
+            BALANCES.with(|b| b.borrow_mut().insert((token, owner), amount));
+        }
+
+        pub fn set_failing(token: AccountId, failing: bool) {
+            FAILING.with(|f| {
+                if failing {
+                    f.borrow_mut().insert(token);
+                } else {
+                    f.borrow_mut().remove(&token);
+                }
+            });
+        }
+
+        pub fn transfer_count(token: AccountId) -> u32 {
+            TRANSFERS.with(|t| t.borrow().get(&token).copied().unwrap_or(0))
+        }
+
+        pub fn mint(token: AccountId, to: AccountId, amount: Balance) -> bool {
+            if FAILING.with(|f| f.borrow().contains(&token)) {
+                return false;
+            }
+            set_balance(token, to, balance_of(token, to) + amount);
+            true
+        }
+
+        pub fn transfer(token: AccountId, from: AccountId, to: AccountId, amount: Balance) -> bool {
+            let from_balance = balance_of(token, from);
+            if from_balance < amount || FAILING.with(|f| f.borrow().contains(&token)) {
+                return false;
+            }
+            set_balance(token, from, from_balance - amount);
+            set_balance(token, to, balance_of(token, to) + amount);
+            TRANSFERS.with(|t| *t.borrow_mut().entry(token).or_insert(0) += 1);
+            true
+        }
+    }
+
     /// Unit tests in Rust are normally defined within such a `#[cfg(test)]`
 /// module and test functions are marked with a `#[test]` attribute.
 /// The below code is technically just normal Rust code.
@@ -713,19 +1921,82 @@ mod capital_stake {
         /// Imports `ink_lang` so we can use `#[ink::test]`.
         use ink_lang as ink;
 
-        /// We test if the default constructor does its job.
-        #[ink::test]
-        fn default_test() {
+        const SIGNER_KEY: [u8; 32] = [0x11; 32];
+
+        type Accounts = ink_env::test::DefaultAccounts<ink_env::DefaultEnvironment>;
+
+        // a stake owned by alice and minting its rewards, with the pool 0 of the lp
+        // token 0x03, of which alice holds 1000
+        fn setup() -> (CapitalStake, Accounts) {
             let mut capital_stake = CapitalStake::new(
-                AccountId::from([0x01; 32]),
+                signer_of(SIGNER_KEY),
                 AccountId::from([0x02; 32]),
                 1,
+                Balance::MAX,
             );
 
             let accounts =
                 ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
                     .expect("Cannot get accounts");
-            capital_stake.owner = accounts.alice;
+
+            capital_stake.add(100, AccountId::from([0x03; 32]), false, 10000);
+            mock::set_balance(AccountId::from([0x03; 32]), accounts.alice, 1000);
+            (capital_stake, accounts)
+        }
+
+        // account of the ecdsa key `secret`, the blake2 hash of its compressed public key
+        fn signer_of(secret: [u8; 32]) -> AccountId {
+            let secp = secp256k1::Secp256k1::new();
+            let key = secp256k1::SecretKey::from_slice(&secret).unwrap();
+            let public_key = secp256k1::PublicKey::from_secret_key(&secp, &key).serialize();
+            let mut signer = <Blake2x256 as HashOutput>::Type::default();
+            ink_env::hash_bytes::<Blake2x256>(&public_key, &mut signer);
+            AccountId::from(signer)
+        }
+
+        // 65 bytes signature of `hash` by the ecdsa key `secret`, with the recovery id last
+        fn sign(secret: [u8; 32], hash: [u8; 32]) -> Vec<u8> {
+            let secp = secp256k1::Secp256k1::new();
+            let key = secp256k1::SecretKey::from_slice(&secret).unwrap();
+            let message = secp256k1::Message::from_slice(&hash).unwrap();
+            let (recovery_id, compact) = secp
+                .sign_recoverable(&message, &key)
+                .serialize_compact();
+            let mut signature = compact.to_vec();
+            signature.push(recovery_id.to_i32() as u8);
+            signature
+        }
+
+        // unstakes `amount` of alice from pool `pid` with a signature of the signer
+        fn signed_unstake(capital_stake: &mut CapitalStake, pid: u32, amount: Balance) {
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+            let hash = capital_stake.unstake_hash(pid, accounts.alice, amount, u64::MAX);
+            capital_stake.unstake(pid, amount, u64::MAX, sign(SIGNER_KEY, hash));
+        }
+
+        fn advance_blocks(count: u32) {
+            for _ in 0..count {
+                ink_env::test::advance_block::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot advance block");
+            }
+        }
+
+        // the Claim events emitted so far, as (pid, amount)
+        fn claims() -> Vec<(u32, Balance)> {
+            ink_env::test::recorded_events()
+                .filter_map(|event| match <Event as scale::Decode>::decode(&mut &event.data[..]) {
+                    Ok(Event::Claim(claim)) => Some((claim.pid, claim.amount)),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        /// We test if the default constructor does its job.
+        #[ink::test]
+        fn default_test() {
+            let (mut capital_stake, _) = setup();
             capital_stake.set_default();
 
             assert_eq!(capital_stake.capacity_max.get(&0).copied().unwrap_or(0), 99999999999999);
@@ -735,16 +2006,7 @@ mod capital_stake {
 
         #[ink::test]
         fn switch_deposit_test() {
-            let mut capital_stake = CapitalStake::new(
-                AccountId::from([0x01; 32]),
-                AccountId::from([0x02; 32]),
-                1,
-            );
-
-            let accounts =
-                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
-                    .expect("Cannot get accounts");
-            capital_stake.owner = accounts.alice;
+            let (mut capital_stake, _) = setup();
 
             assert_eq!(capital_stake.can_deposit, true);
 
@@ -755,17 +2017,7 @@ mod capital_stake {
 
         #[ink::test]
         fn set_user_capacity_max_test() {
-            let mut capital_stake = CapitalStake::new(
-                AccountId::from([0x01; 32]),
-                AccountId::from([0x02; 32]),
-                1,
-            );
-
-            let accounts =
-                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
-                    .expect("Cannot get accounts");
-
-            capital_stake.owner = accounts.alice;
+            let (mut capital_stake, _) = setup();
 
             capital_stake.set_user_capacity_max(0, 10000);
 
@@ -774,17 +2026,7 @@ mod capital_stake {
 
         #[ink::test]
         fn set_capacity_max_test() {
-            let mut capital_stake = CapitalStake::new(
-                AccountId::from([0x01; 32]),
-                AccountId::from([0x02; 32]),
-                1,
-            );
-
-            let accounts =
-                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
-                    .expect("Cannot get accounts");
-
-            capital_stake.owner = accounts.alice;
+            let (mut capital_stake, _) = setup();
 
             capital_stake.set_capacity_max(0, 10000);
 
@@ -793,38 +2035,33 @@ mod capital_stake {
 
         #[ink::test]
         fn update_block_reward_test() {
-            let mut capital_stake = CapitalStake::new(
-                AccountId::from([0x01; 32]),
-                AccountId::from([0x02; 32]),
-                1,
-            );
+            let (mut capital_stake, _) = setup();
 
-            let accounts =
-                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
-                    .expect("Cannot get accounts");
-
-            capital_stake.owner = accounts.alice;
-
-            assert_eq!(capital_stake.nsure_per_block, 18 * 10u128.saturating_pow(10));
+            assert_eq!(capital_stake.emission_rate(), 18 * 10u128.saturating_pow(10));
 
             capital_stake.update_block_reward(12 * 10u128.saturating_pow(10));
 
-            assert_eq!(capital_stake.nsure_per_block, 12 * 10u128.saturating_pow(10));
+            assert_eq!(capital_stake.emission_rate(), 12 * 10u128.saturating_pow(10));
+
+            // a later update starts a new segment at its block
+            advance_blocks(2);
+            capital_stake.update_block_reward(50);
+            assert_eq!(capital_stake.emission_schedule().len(), 2);
+            assert_eq!(capital_stake.get_multiplier(0, 4), 24 * 10u128.saturating_pow(10) + 100);
         }
 
         #[ink::test]
-        fn update_withdraw_pending_test() {
-            let mut capital_stake = CapitalStake::new(
-                AccountId::from([0x01; 32]),
-                AccountId::from([0x02; 32]),
-                1,
-            );
+        #[should_panic(expected = "segment scheduled")]
+        fn update_block_reward_scheduled_test() {
+            let (mut capital_stake, _) = setup();
+            capital_stake.add_emission_segment(10, 40);
 
-            let accounts =
-                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
-                    .expect("Cannot get accounts");
+            capital_stake.update_block_reward(100);
+        }
 
-            capital_stake.owner = accounts.alice;
+        #[ink::test]
+        fn update_withdraw_pending_test() {
+            let (mut capital_stake, _) = setup();
 
             assert_eq!(capital_stake.pending_duration, 10);
 
@@ -832,5 +2069,368 @@ mod capital_stake {
 
             assert_eq!(capital_stake.pending_duration, 20);
         }
+
+        #[ink::test]
+        fn multi_pool_positions_test() {
+            let (mut capital_stake, accounts) = setup();
+            capital_stake.add(100, AccountId::from([0x04; 32]), false, 10000);
+            mock::set_balance(AccountId::from([0x04; 32]), accounts.alice, 1000);
+            capital_stake.update_block_reward(100);
+
+            capital_stake.deposit(0, 100);
+            advance_blocks(2);
+            capital_stake.deposit(1, 50);
+            advance_blocks(1);
+
+            assert_eq!(capital_stake.get_user_info(0, accounts.alice).0, 100);
+            assert_eq!(capital_stake.get_user_info(1, accounts.alice).0, 50);
+            assert_eq!(capital_stake.get_user_info(0, accounts.bob).0, 0);
+            assert_eq!(mock::balance_of(AccountId::from([0x03; 32]), accounts.alice), 900);
+            assert_eq!(mock::balance_of(AccountId::from([0x04; 32]), accounts.alice), 950);
+            // each pool earns half of 100 per block, pool 1 only since its deposit
+            assert_eq!(capital_stake.pending_nsure(0, accounts.alice), 100);
+            assert_eq!(capital_stake.pending_nsure(1, accounts.alice), 50);
+
+            // claiming pool 1 leaves the reward in pool 0 alone
+            capital_stake.claim(1);
+            assert_eq!(mock::balance_of(AccountId::from([0x02; 32]), accounts.alice), 50);
+            assert_eq!(capital_stake.pending_nsure(1, accounts.alice), 0);
+            assert_eq!(capital_stake.get_pool_info(1).0, 50);
+            assert_eq!(capital_stake.pending_nsure(0, accounts.alice), 100);
+        }
+
+        #[ink::test]
+        fn unbond_requests_test() {
+            let (mut capital_stake, accounts) = setup();
+            capital_stake.deposit(0, 100);
+
+            signed_unstake(&mut capital_stake, 0, 40);
+            capital_stake.update_withdraw_pending(0);
+            signed_unstake(&mut capital_stake, 0, 30);
+
+            let requests = capital_stake.unbond_requests_of(0, accounts.alice);
+            assert_eq!(requests.len(), 2);
+            assert_eq!(capital_stake.get_user_info(0, accounts.alice).0, 30);
+            assert_eq!(capital_stake.get_user_info(0, accounts.alice).2, 70);
+            assert_eq!(capital_stake.get_pool_info(0).0, 30);
+
+            // only the request made without pending period has matured
+            assert_eq!(capital_stake.withdraw(0), 30);
+            assert_eq!(mock::balance_of(AccountId::from([0x03; 32]), accounts.alice), 930);
+            assert_eq!(capital_stake.unbond_requests_of(0, accounts.alice), vec![requests[0]]);
+            assert_eq!(capital_stake.get_user_info(0, accounts.alice).2, 40);
+            assert_eq!(capital_stake.get_pool_info(0).5, 40);
+
+            while ink_env::block_timestamp::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get block timestamp")
+                < requests[0].unlock_at
+            {
+                advance_blocks(1);
+            }
+            assert_eq!(capital_stake.withdraw(0), 40);
+            assert_eq!(mock::balance_of(AccountId::from([0x03; 32]), accounts.alice), 970);
+            assert!(capital_stake.unbond_requests_of(0, accounts.alice).is_empty());
+            assert_eq!(capital_stake.get_pool_info(0).5, 0);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "still pending")]
+        fn withdraw_pending_test() {
+            let (mut capital_stake, _) = setup();
+            capital_stake.deposit(0, 100);
+            signed_unstake(&mut capital_stake, 0, 40);
+
+            capital_stake.withdraw(0);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "signature expired")]
+        fn unstake_signature_expired_test() {
+            let (mut capital_stake, accounts) = setup();
+            capital_stake.deposit(0, 100);
+
+            assert_eq!(capital_stake.nonce_of(accounts.alice), 0);
+            advance_blocks(1);
+            capital_stake.unstake(0, 40, 0, vec![0u8; 65]);
+        }
+
+        #[ink::test]
+        fn unstake_signature_test() {
+            let (mut capital_stake, accounts) = setup();
+            capital_stake.deposit(0, 100);
+
+            let hash = capital_stake.unstake_hash(0, accounts.alice, 40, 100);
+            capital_stake.unstake(0, 40, 100, sign(SIGNER_KEY, hash));
+
+            assert_eq!(capital_stake.nonce_of(accounts.alice), 1);
+            assert_eq!(capital_stake.get_user_info(0, accounts.alice).0, 60);
+            assert_eq!(capital_stake.unbond_requests_of(0, accounts.alice)[0].amount, 40);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "invalid signer")]
+        fn unstake_wrong_signer_test() {
+            let (mut capital_stake, accounts) = setup();
+            capital_stake.deposit(0, 100);
+
+            let hash = capital_stake.unstake_hash(0, accounts.alice, 40, 100);
+            capital_stake.unstake(0, 40, 100, sign([0x22; 32], hash));
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "invalid signer")]
+        fn unstake_nonce_reuse_test() {
+            let (mut capital_stake, accounts) = setup();
+            capital_stake.deposit(0, 100);
+
+            let signature = sign(SIGNER_KEY, capital_stake.unstake_hash(0, accounts.alice, 20, 100));
+            capital_stake.unstake(0, 20, 100, signature.clone());
+            // the nonce moved on, so the same signature recovers another key
+            capital_stake.unstake(0, 20, 100, signature);
+        }
+
+        #[ink::test]
+        fn emission_schedule_test() {
+            let (mut capital_stake, _) = setup();
+            capital_stake.update_block_reward(100);
+            capital_stake.add_emission_segment(10, 40);
+            capital_stake.set_halving_interval(4);
+            capital_stake.set_end_block(20);
+
+            // 4 blocks at 100, 4 blocks at 50, then 2 blocks at 25
+            assert_eq!(capital_stake.get_multiplier(0, 10), 650);
+            // the second segment halves from its own start block
+            assert_eq!(capital_stake.get_multiplier(8, 12), 25 + 25 + 40 + 40);
+            // nothing is emitted after the end block
+            assert_eq!(capital_stake.get_multiplier(18, 30), 10 + 10);
+        }
+
+        #[ink::test]
+        fn reward_source_test() {
+            let (mut capital_stake, accounts) = setup();
+
+            // nothing can be minted with a zero cap
+            capital_stake.set_mint_cap(0);
+            assert_eq!(capital_stake.obtain_reward(100), Some(0));
+
+            capital_stake.set_reward_source(RewardSource::Reserve);
+            mock::set_balance(AccountId::from([0x02; 32]), accounts.alice, 60);
+            capital_stake.fund_reserve(60);
+            capital_stake.update_block_reward(20);
+            assert_eq!(capital_stake.reward_runway(), 3);
+
+            assert_eq!(capital_stake.obtain_reward(100), Some(60));
+            assert_eq!(capital_stake.reward_reserve(), 0);
+            assert_eq!(capital_stake.reward_debt_total(), 60);
+            assert_eq!(capital_stake.reward_runway(), 0);
+        }
+
+        #[ink::test]
+        fn pending_rewards_test() {
+            let (mut capital_stake, accounts) = setup();
+            capital_stake.update_block_reward(100);
+            capital_stake.add_extra_reward(0, AccountId::from([0x04; 32]), 10);
+            mock::set_balance(AccountId::from([0x04; 32]), accounts.alice, 25);
+            capital_stake.fund_extra_reward(0, 0, 25);
+            capital_stake.deposit(0, 100);
+
+            advance_blocks(4);
+
+            // 3 blocks since the start block, the extra reward capped by its reserve
+            assert_eq!(
+                capital_stake.pending_rewards(0, accounts.alice),
+                vec![
+                    (AccountId::from([0x02; 32]), 300),
+                    (AccountId::from([0x04; 32]), 25)
+                ]
+            );
+
+            // a reward whose transfer failed is still pending
+            mock::set_failing(AccountId::from([0x04; 32]), true);
+            capital_stake.claim(0);
+            assert_eq!(mock::balance_of(AccountId::from([0x02; 32]), accounts.alice), 300);
+            assert_eq!(capital_stake.pending_rewards(0, accounts.alice)[1].1, 25);
+
+            mock::set_failing(AccountId::from([0x04; 32]), false);
+            capital_stake.claim(0);
+            assert_eq!(mock::balance_of(AccountId::from([0x04; 32]), accounts.alice), 25);
+            assert_eq!(capital_stake.pending_rewards(0, accounts.alice)[1].1, 0);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "token is staked")]
+        fn add_extra_reward_staked_token_test() {
+            let (mut capital_stake, _) = setup();
+            capital_stake.add(100, AccountId::from([0x04; 32]), false, 10000);
+            capital_stake.add_extra_reward(0, AccountId::from([0x04; 32]), 10);
+        }
+
+        #[ink::test]
+        fn locked_weight_test() {
+            let (mut capital_stake, accounts) = setup();
+
+            assert_eq!(capital_stake.boost_for(0), 10000);
+            assert_eq!(capital_stake.boost_for(2_628_000), 17500);
+            assert_eq!(capital_stake.boost_for(5_256_000), 25000);
+
+            capital_stake.deposit_locked(0, 40, 5_256_000);
+            capital_stake.deposit(0, 60);
+            capital_stake.settle_position(0, accounts.bob, 100);
+
+            assert_eq!(capital_stake.weight_of_user(0, accounts.alice), 60 + 100);
+            assert_eq!(capital_stake.weight_of_user(0, accounts.bob), 100);
+            assert_eq!(capital_stake.pool_info[0].total_weight, 260);
+
+            // rewards are shared by weight
+            capital_stake.pool_info[0].acc_nsure_per_share = 10u128.saturating_pow(12);
+            assert_eq!(capital_stake.pending_nsure(0, accounts.alice), 160);
+            assert_eq!(capital_stake.pending_nsure(0, accounts.bob), 100);
+        }
+
+        #[ink::test]
+        fn claim_many_test() {
+            let (mut capital_stake, accounts) = setup();
+            capital_stake.add(100, AccountId::from([0x04; 32]), false, 10000);
+            capital_stake.add(100, AccountId::from([0x05; 32]), false, 10000);
+            mock::set_balance(AccountId::from([0x05; 32]), accounts.alice, 1000);
+            capital_stake.update_block_reward(300);
+
+            capital_stake.deposit(0, 100);
+            capital_stake.deposit(2, 50);
+            advance_blocks(3);
+
+            // each pool earns 100 per block since the start block
+            assert_eq!(capital_stake.claim_many(vec![0, 1]), 200);
+            // pool 1 has no position, so it has no Claim event
+            assert_eq!(claims(), vec![(0, 200)]);
+            assert_eq!(mock::balance_of(AccountId::from([0x02; 32]), accounts.alice), 200);
+            assert_eq!(capital_stake.pending_nsure(0, accounts.alice), 0);
+            assert_eq!(capital_stake.pending_nsure(2, accounts.alice), 200);
+
+            assert_eq!(capital_stake.claim_all(), 200);
+            assert_eq!(claims(), vec![(0, 200), (0, 0), (2, 200)]);
+            assert_eq!(mock::balance_of(AccountId::from([0x02; 32]), accounts.alice), 400);
+            assert_eq!(capital_stake.pending_nsure(2, accounts.alice), 0);
+            // a single NSURE transfer for each call
+            assert_eq!(mock::transfer_count(AccountId::from([0x02; 32])), 2);
+        }
+
+        #[ink::test]
+        fn claim_many_extra_rewards_test() {
+            let (mut capital_stake, accounts) = setup();
+            capital_stake.add(100, AccountId::from([0x04; 32]), false, 10000);
+            mock::set_balance(AccountId::from([0x04; 32]), accounts.alice, 1000);
+            mock::set_balance(AccountId::from([0x06; 32]), accounts.alice, 200);
+            for pid in 0..2 {
+                capital_stake.add_extra_reward(pid, AccountId::from([0x06; 32]), 10);
+                capital_stake.fund_extra_reward(pid, 0, 100);
+            }
+            capital_stake.deposit(0, 100);
+            capital_stake.deposit(1, 50);
+            advance_blocks(3);
+
+            let transfers = mock::transfer_count(AccountId::from([0x06; 32]));
+            capital_stake.claim_all();
+
+            // 2 blocks of 10 in each pool, paid with a single transfer
+            assert_eq!(mock::balance_of(AccountId::from([0x06; 32]), accounts.alice), 40);
+            assert_eq!(mock::transfer_count(AccountId::from([0x06; 32])), transfers + 1);
+            let extra_claims: Vec<(u32, Balance)> = ink_env::test::recorded_events()
+                .filter_map(|event| match <Event as scale::Decode>::decode(&mut &event.data[..]) {
+                    Ok(Event::ClaimExtraReward(claim)) => Some((claim.pid, claim.amount)),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(extra_claims, vec![(0, 20), (1, 20)]);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "duplicate pid")]
+        fn claim_many_duplicate_test() {
+            let (mut capital_stake, _) = setup();
+            capital_stake.add(100, AccountId::from([0x04; 32]), false, 10000);
+
+            capital_stake.claim_many(vec![1, 1]);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "not in emergency")]
+        fn emergency_withdraw_test() {
+            let (mut capital_stake, _) = setup();
+            capital_stake.deposit(0, 100);
+
+            capital_stake.emergency_withdraw(0);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "nothing staked")]
+        fn emergency_withdraw_empty_test() {
+            let (mut capital_stake, _) = setup();
+            capital_stake.set_emergency(true);
+            assert_eq!(capital_stake.emergency(), true);
+
+            capital_stake.emergency_withdraw(0);
+        }
+
+        #[ink::test]
+        fn emergency_withdraw_position_test() {
+            let (mut capital_stake, accounts) = setup();
+            capital_stake.set_treasury(accounts.bob);
+            capital_stake.deposit_locked(0, 40, 100);
+            capital_stake.deposit(0, 60);
+            advance_blocks(2);
+
+            capital_stake.set_emergency(true);
+            assert_eq!(capital_stake.emergency_withdraw(0), 100);
+
+            // the locked part is returned too, without penalty
+            assert_eq!(mock::balance_of(AccountId::from([0x03; 32]), accounts.alice), 1000);
+            assert_eq!(mock::balance_of(AccountId::from([0x03; 32]), accounts.bob), 0);
+            assert_eq!(capital_stake.get_user_info(0, accounts.alice).0, 0);
+            assert_eq!(capital_stake.weight_of_user(0, accounts.alice), 0);
+            assert_eq!(capital_stake.lock_of(0, accounts.alice), None);
+            assert_eq!(capital_stake.pending_nsure(0, accounts.alice), 0);
+            assert_eq!(capital_stake.get_pool_info(0).0, 0);
+
+            let withdrawals: Vec<(AccountId, u32, Balance)> = ink_env::test::recorded_events()
+                .filter_map(|event| match <Event as scale::Decode>::decode(&mut &event.data[..]) {
+                    Ok(Event::EmergencyWithdraw(withdraw)) => {
+                        Some((withdraw.user, withdraw.pid, withdraw.amount))
+                    }
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(withdrawals, vec![(accounts.alice, 0, 100)]);
+        }
+
+        #[ink::test]
+        fn forfeit_position_test() {
+            let (mut capital_stake, accounts) = setup();
+            capital_stake.settle_position(0, accounts.alice, 100);
+            capital_stake.settle_position(0, accounts.bob, 50);
+            capital_stake.pool_info[0].acc_nsure_per_share = 10u128.saturating_pow(12);
+            capital_stake.reward_debt_total = 150;
+
+            assert_eq!(capital_stake.forfeit_position(0, accounts.alice), 100);
+            assert_eq!(capital_stake.get_user_info(0, accounts.alice).0, 0);
+            assert_eq!(capital_stake.pending_nsure(0, accounts.alice), 0);
+            assert_eq!(capital_stake.pool_info[0].amount, 50);
+            assert_eq!(capital_stake.pool_info[0].total_weight, 50);
+
+            // the forfeited reward funds later rewards, the reward of bob is untouched
+            assert_eq!(capital_stake.reward_debt_total(), 50);
+            assert_eq!(capital_stake.reward_reserve(), 100);
+            assert_eq!(capital_stake.pending_nsure(0, accounts.bob), 50);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "unstake: insufficient assets")]
+        fn unbond_locked_test() {
+            let (mut capital_stake, _) = setup();
+            capital_stake.deposit_locked(0, 40, 100);
+            capital_stake.deposit(0, 60);
+
+            signed_unstake(&mut capital_stake, 0, 70);
+        }
     }
 }
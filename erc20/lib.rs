@@ -14,7 +14,7 @@
 
 #![cfg_attr(not(feature = "std"), no_std)]
 
-pub use self::erc20::Erc20;
+pub use self::erc20::{Erc20, Error};
 use ink_lang as ink;
 
 #[ink::contract]
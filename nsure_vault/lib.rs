@@ -0,0 +1,878 @@
+//! nsure_vault stakes NSURE in the NSURE pool of underwrite and compounds the rewards, depositors get vault shares.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub use self::nsure_vault::NsureVault;
+use ink_lang as ink;
+
+#[ink::contract]
+mod nsure_vault {
+    use erc20::Erc20;
+    use ink_env::call::FromAccountId;
+    use ink_prelude::vec::Vec;
+    use ink_storage::traits::{PackedLayout, SpreadLayout};
+    use primitive_types::U256;
+    use underwrite::Underwrite;
+
+    #[cfg(not(feature = "ink-as-dependency"))]
+    use ink_storage::{collections::HashMap as StorageHashMap, lazy::Lazy};
+
+    #[ink(event)]
+    pub struct Deposit {
+        #[ink(topic)]
+        user: AccountId,
+        #[ink(topic)]
+        amount: Balance,
+        #[ink(topic)]
+        shares: Balance,
+    }
+
+    #[ink(event)]
+    pub struct WithdrawRequested {
+        #[ink(topic)]
+        user: AccountId,
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        amount: Balance,
+        #[ink(topic)]
+        shares: Balance,
+    }
+
+    #[ink(event)]
+    pub struct WithdrawSettled {
+        #[ink(topic)]
+        user: AccountId,
+        #[ink(topic)]
+        id: u64,
+        #[ink(topic)]
+        amount: Balance,
+    }
+
+    #[ink(event)]
+    pub struct UnstakeBatch {
+        #[ink(topic)]
+        amount: Balance,
+        #[ink(topic)]
+        withdrawal_tail: u64,
+    }
+
+    #[ink(event)]
+    pub struct Harvest {
+        #[ink(topic)]
+        caller: AccountId,
+        #[ink(topic)]
+        reward: Balance,
+        #[ink(topic)]
+        bounty: Balance,
+    }
+
+    #[ink(event)]
+    pub struct SetBounty {
+        #[ink(topic)]
+        bounty_bps: u128,
+    }
+
+    #[ink(event)]
+    pub struct SetEpochLength {
+        #[ink(topic)]
+        blocks: BlockNumber,
+    }
+
+    // NSURE unstaked for `owner`, paid once released by underwrite.
+    #[derive(
+        Debug, PartialEq, Eq, scale::Encode, scale::Decode, Clone, Copy, SpreadLayout, PackedLayout,
+    )]
+    #[cfg_attr(
+        feature = "std",
+        derive(scale_info::TypeInfo, ink_storage::traits::StorageLayout)
+    )]
+    pub struct Withdrawal {
+        pub owner: AccountId,
+        pub amount: Balance,
+    }
+
+    /// Denominator of ratios expressed in basis points.
+    pub const BPS_DENOMINATOR: u128 = 10000;
+    /// Hard cap on the harvest bounty, 5%.
+    pub const MAX_BOUNTY_BPS: u128 = 500;
+    /// Shares of the first deposit locked in the vault for good, so the share price
+    /// can't be inflated by donating NSURE to a nearly empty vault.
+    pub const MINIMUM_SHARES: Balance = 1000;
+
+    #[ink(storage)]
+    pub struct NsureVault {
+        nsure: Lazy<Erc20>,
+        underwrite: Lazy<Underwrite>,
+        underwrite_account: AccountId,
+        // the NSURE pool of underwrite
+        pid: u32,
+        total_supply: Balance,
+        balances: StorageHashMap<AccountId, Balance>,
+        // share of each harvest paid to its caller, in basis points
+        bounty_bps: u128,
+        owner: AccountId,
+        // withdrawals settled first-in-first-out, from `withdrawal_head` up to `withdrawal_tail`
+        withdrawals: StorageHashMap<u64, Withdrawal>,
+        withdrawal_head: u64,
+        withdrawal_tail: u64,
+        // NSURE still to be paid to the withdrawals, by account
+        pending_withdrawals: StorageHashMap<AccountId, Balance>,
+        // NSURE requested since the last batch, still staked in underwrite
+        queued: Balance,
+        // withdrawals are unstaked from underwrite in one batch per epoch, so the vault
+        // uses one unbond request and one signature per epoch whatever the users do
+        epoch_length: BlockNumber,
+        last_batch: BlockNumber,
+        // NSURE released by underwrite and held for the withdrawals
+        released: Balance,
+    }
+
+    impl NsureVault {
+        #[ink(constructor)]
+        pub fn new(nsure: AccountId, underwrite: AccountId, pid: u32) -> Self {
+            let nsure: Erc20 = FromAccountId::from_account_id(nsure);
+            let underwrite_contract: Underwrite = FromAccountId::from_account_id(underwrite);
+            Self {
+                nsure: Lazy::new(nsure),
+                underwrite: Lazy::new(underwrite_contract),
+                underwrite_account: underwrite,
+                pid,
+                total_supply: 0,
+                balances: StorageHashMap::new(),
+                bounty_bps: 50,
+                owner: Self::env().caller(),
+                withdrawals: StorageHashMap::new(),
+                withdrawal_head: 0,
+                withdrawal_tail: 0,
+                pending_withdrawals: StorageHashMap::new(),
+                queued: 0,
+                // about one day of 6s blocks
+                epoch_length: 14400,
+                last_batch: 0,
+                released: 0,
+            }
+        }
+
+        #[ink(message)]
+        pub fn total_supply(&self) -> Balance {
+            self.total_supply
+        }
+
+        #[ink(message)]
+        pub fn balance_of(&self, owner: AccountId) -> Balance {
+            self.balances.get(&owner).copied().unwrap_or(0)
+        }
+
+        /// Returns the NSURE backing the shares: staked, pending rewards without the
+        /// harvest bounty and idle, less what the queued withdrawals are owed.
+        #[ink(message)]
+        pub fn total_assets(&self) -> Balance {
+            self.net_assets(self.staked(), self.pending_reward(), self.idle())
+        }
+
+        fn net_assets(&self, staked: Balance, pending: Balance, idle: Balance) -> Balance {
+            let bounty = pending * self.bounty_bps / BPS_DENOMINATOR;
+            (staked + pending - bounty + idle).saturating_sub(self.queued)
+        }
+
+        /// Returns the NSURE `shares` are worth.
+        #[ink(message)]
+        pub fn assets_for(&self, shares: Balance) -> Balance {
+            if self.total_supply == 0 {
+                return shares;
+            }
+            Self::mul_div(shares, self.total_assets(), self.total_supply)
+        }
+
+        /// Stakes `amount` NSURE of the caller and returns the shares minted.
+        /// `MINIMUM_SHARES` of the first deposit are locked in the vault.
+        #[ink(message)]
+        pub fn deposit(&mut self, amount: Balance) -> Balance {
+            assert!(amount > 0, "NsureVault: Cannot deposit 0.");
+            let caller = self.env().caller();
+
+            let assets = self.total_assets();
+            self.nsure_transfer_from(caller, amount);
+            let shares = if self.total_supply == 0 {
+                assert!(amount > MINIMUM_SHARES, "NsureVault: Deposit too small.");
+                self.total_supply = MINIMUM_SHARES;
+                amount - MINIMUM_SHARES
+            } else if assets == 0 {
+                amount
+            } else {
+                Self::mul_div(amount, self.total_supply, assets)
+            };
+            assert!(shares > 0, "NsureVault: Cannot mint 0.");
+
+            let balance = self.balance_of(caller);
+            self.balances.insert(caller, balance + shares);
+            self.total_supply += shares;
+            self.stake_idle();
+
+            self.env().emit_event(Deposit {
+                user: caller,
+                amount,
+                shares,
+            });
+            shares
+        }
+
+        /// Burns the caller's shares worth `amount` NSURE and queues the withdrawal.
+        /// The NSURE is unstaked from underwrite by the next `unstake_batch` and paid
+        /// by `settle_withdrawals` once underwrite releases it.
+        ///
+        /// Returns the id of the withdrawal.
+        #[ink(message)]
+        pub fn request_withdraw(&mut self, amount: Balance) -> u64 {
+            assert!(amount > 0, "NsureVault: Cannot withdraw 0.");
+            let caller = self.env().caller();
+
+            // only the staked NSURE can be unstaked, rewards are staked by `harvest`
+            assert!(self.queued + amount <= self.staked(), "insufficient staked");
+
+            let assets = self.total_assets();
+            let shares = self.shares_to_burn(amount, assets);
+            let balance = self.balance_of(caller);
+            assert!(balance >= shares, "insufficient shares");
+            self.balances.insert(caller, balance - shares);
+            self.total_supply -= shares;
+            self.queued += amount;
+
+            let id = self.withdrawal_tail;
+            self.withdrawals.insert(
+                id,
+                Withdrawal {
+                    owner: caller,
+                    amount,
+                },
+            );
+            self.withdrawal_tail += 1;
+            let pending = self.pending_withdrawal(caller);
+            self.pending_withdrawals.insert(caller, pending + amount);
+
+            self.env().emit_event(WithdrawRequested {
+                user: caller,
+                id,
+                amount,
+                shares,
+            });
+            id
+        }
+
+        /// Unstakes the NSURE of the withdrawals requested since the last batch from
+        /// underwrite, at most once per epoch. Anyone can call it.
+        ///
+        /// `signature` is the signature of the underwrite signer for unstaking
+        /// `queued` for this vault, see `Underwrite::unstake_hash`.
+        #[ink(message)]
+        pub fn unstake_batch(&mut self, deadline: u64, signature: Vec<u8>) -> Balance {
+            let block_number = self.env().block_number();
+            assert!(self.queued > 0, "nothing queued");
+            assert!(
+                self.last_batch == 0 || block_number >= self.last_batch + self.epoch_length,
+                "epoch not over"
+            );
+
+            let amount = self.queued;
+            self.unstake(amount, deadline, signature);
+            self.queued = 0;
+            self.last_batch = block_number;
+
+            self.env().emit_event(UnstakeBatch {
+                amount,
+                withdrawal_tail: self.withdrawal_tail,
+            });
+            amount
+        }
+
+        /// Withdraws the NSURE released by underwrite and pays up to `max_count`
+        /// withdrawals in request order. Anyone can call it.
+        ///
+        /// Returns the number of withdrawals paid.
+        #[ink(message)]
+        pub fn settle_withdrawals(&mut self, max_count: u32) -> u32 {
+            self.released += self.withdraw_matured();
+
+            let mut settled = 0;
+            while settled < max_count && self.withdrawal_head < self.withdrawal_tail {
+                let id = self.withdrawal_head;
+                let withdrawal = *self.withdrawals.get(&id).unwrap();
+                if withdrawal.amount > self.released {
+                    break;
+                }
+                self.nsure_transfer(withdrawal.owner, withdrawal.amount);
+                self.released -= withdrawal.amount;
+                let pending = self.pending_withdrawal(withdrawal.owner);
+                self.pending_withdrawals
+                    .insert(withdrawal.owner, pending - withdrawal.amount);
+                self.withdrawals.take(&id);
+                self.withdrawal_head += 1;
+                settled += 1;
+
+                self.env().emit_event(WithdrawSettled {
+                    user: withdrawal.owner,
+                    id,
+                    amount: withdrawal.amount,
+                });
+            }
+            settled
+        }
+
+        /// Claims the NSURE rewards of the vault and stakes them again, the caller gets
+        /// `bounty_bps` of the rewards. Anyone can call it.
+        ///
+        /// Returns the bounty paid.
+        #[ink(message)]
+        pub fn harvest(&mut self) -> Balance {
+            let caller = self.env().caller();
+            let before = self.idle();
+            self.claim();
+            let reward = self.idle() - before;
+
+            let bounty = reward * self.bounty_bps / BPS_DENOMINATOR;
+            if bounty > 0 {
+                self.nsure_transfer(caller, bounty);
+            }
+            self.stake_idle();
+
+            self.env().emit_event(Harvest {
+                caller,
+                reward,
+                bounty,
+            });
+            bounty
+        }
+
+        /// Returns the NSURE requested and not yet paid to `owner`.
+        #[ink(message)]
+        pub fn pending_withdrawal(&self, owner: AccountId) -> Balance {
+            self.pending_withdrawals.get(&owner).copied().unwrap_or(0)
+        }
+
+        #[ink(message)]
+        pub fn withdrawal(&self, id: u64) -> Option<Withdrawal> {
+            self.withdrawals.get(&id).copied()
+        }
+
+        /// Returns the ids of the first unpaid withdrawal and of the next one.
+        #[ink(message)]
+        pub fn withdrawal_queue(&self) -> (u64, u64) {
+            (self.withdrawal_head, self.withdrawal_tail)
+        }
+
+        #[ink(message)]
+        pub fn released(&self) -> Balance {
+            self.released
+        }
+
+        /// Returns the NSURE requested since the last batch.
+        #[ink(message)]
+        pub fn queued(&self) -> Balance {
+            self.queued
+        }
+
+        /// Returns the epoch length and the block of the last batch.
+        #[ink(message)]
+        pub fn batch_epoch(&self) -> (BlockNumber, BlockNumber) {
+            (self.epoch_length, self.last_batch)
+        }
+
+        #[ink(message)]
+        pub fn set_epoch_length(&mut self, blocks: BlockNumber) {
+            self.only_owner();
+            assert!(blocks > 0, "invalid epoch length");
+            self.epoch_length = blocks;
+            self.env().emit_event(SetEpochLength { blocks });
+        }
+
+        #[ink(message)]
+        pub fn bounty_bps(&self) -> u128 {
+            self.bounty_bps
+        }
+
+        #[ink(message)]
+        pub fn set_bounty_bps(&mut self, bounty_bps: u128) {
+            self.only_owner();
+            assert!(bounty_bps <= MAX_BOUNTY_BPS, "bounty too high");
+            self.bounty_bps = bounty_bps;
+            self.env().emit_event(SetBounty { bounty_bps });
+        }
+
+        #[ink(message)]
+        pub fn underwrite(&self) -> AccountId {
+            self.underwrite_account
+        }
+
+        #[ink(message)]
+        pub fn pid(&self) -> u32 {
+            self.pid
+        }
+
+        #[ink(message)]
+        pub fn owner(&self) -> Option<AccountId> {
+            Some(self.owner)
+        }
+
+        #[ink(message)]
+        pub fn transfer_ownership(&mut self, new_owner: Option<AccountId>) {
+            self.only_owner();
+            if let Some(owner) = new_owner {
+                self.owner = owner;
+            }
+        }
+
+        // NSURE held by the vault and not owed to the withdrawals
+        fn idle(&self) -> Balance {
+            self.nsure_balance().saturating_sub(self.released)
+        }
+
+        fn stake_idle(&mut self) {
+            let idle = self.idle();
+            if idle == 0 {
+                return;
+            }
+            self.stake(idle);
+        }
+
+        // the calls into NSURE and underwrite go through the helpers below, the unit
+        // tests swap them for the `mock` module as they can't reach other contracts
+        // off-chain.
+
+        #[cfg(not(test))]
+        fn nsure_balance(&self) -> Balance {
+            self.nsure.balance_of(self.env().account_id())
+        }
+
+        #[cfg(not(test))]
+        fn nsure_transfer(&mut self, to: AccountId, amount: Balance) {
+            assert!(
+                self.nsure.transfer(to, amount).is_ok(),
+                "transfer operation did not succeed"
+            );
+        }
+
+        // pulls `amount` NSURE of `from` into the vault
+        #[cfg(not(test))]
+        fn nsure_transfer_from(&mut self, from: AccountId, amount: Balance) {
+            let self_account = self.env().account_id();
+            assert!(
+                self.nsure.transfer_from(from, self_account, amount).is_ok(),
+                "transfer_from operation did not succeed"
+            );
+        }
+
+        // NSURE of the vault staked in underwrite
+        #[cfg(not(test))]
+        fn staked(&self) -> Balance {
+            let (staked, ..) = self.underwrite.get_user_info(self.pid, self.env().account_id());
+            staked
+        }
+
+        // NSURE rewards of the vault pending in underwrite
+        #[cfg(not(test))]
+        fn pending_reward(&self) -> Balance {
+            self.underwrite.pending_nsure(self.pid, self.env().account_id())
+        }
+
+        #[cfg(not(test))]
+        fn stake(&mut self, amount: Balance) {
+            assert!(
+                self.nsure.approve(self.underwrite_account, amount).is_ok(),
+                "approve operation did not succeed"
+            );
+            self.underwrite.deposit(self.pid, amount);
+        }
+
+        #[cfg(not(test))]
+        fn unstake(&mut self, amount: Balance, deadline: u64, signature: Vec<u8>) {
+            self.underwrite.unstake(self.pid, amount, deadline, signature);
+        }
+
+        // withdraws the unstaked NSURE underwrite released, if any, and returns it
+        #[cfg(not(test))]
+        fn withdraw_matured(&mut self) -> Balance {
+            let timestamp = self.env().block_timestamp();
+            let matured = self
+                .underwrite
+                .unbond_requests_of(self.pid, self.env().account_id())
+                .iter()
+                .any(|request| request.unlock_at <= timestamp);
+            if !matured {
+                return 0;
+            }
+            self.underwrite.withdraw(self.pid)
+        }
+
+        #[cfg(not(test))]
+        fn claim(&mut self) {
+            self.underwrite.claim(self.pid);
+        }
+
+        #[cfg(test)]
+        fn nsure_balance(&self) -> Balance {
+            mock::balance_of(self.env().account_id())
+        }
+
+        #[cfg(test)]
+        fn nsure_transfer(&mut self, to: AccountId, amount: Balance) {
+            mock::transfer(self.env().account_id(), to, amount)
+        }
+
+        #[cfg(test)]
+        fn nsure_transfer_from(&mut self, from: AccountId, amount: Balance) {
+            mock::transfer(from, self.env().account_id(), amount)
+        }
+
+        #[cfg(test)]
+        fn staked(&self) -> Balance {
+            mock::with(|s| s.staked)
+        }
+
+        #[cfg(test)]
+        fn pending_reward(&self) -> Balance {
+            mock::with(|s| s.reward)
+        }
+
+        #[cfg(test)]
+        fn stake(&mut self, amount: Balance) {
+            mock::stake(self.env().account_id(), amount)
+        }
+
+        #[cfg(test)]
+        fn unstake(&mut self, amount: Balance, _deadline: u64, _signature: Vec<u8>) {
+            mock::unstake(amount)
+        }
+
+        #[cfg(test)]
+        fn withdraw_matured(&mut self) -> Balance {
+            mock::withdraw_matured(self.env().account_id())
+        }
+
+        #[cfg(test)]
+        fn claim(&mut self) {
+            mock::claim(self.env().account_id())
+        }
+
+        // shares worth `amount` of `assets`, rounded up so the remaining shares are
+        // never worth more than before
+        fn shares_to_burn(&self, amount: Balance, assets: Balance) -> Balance {
+            assert!(amount <= assets, "insufficient shares");
+            Self::mul_div_up(amount, self.total_supply, assets)
+        }
+
+        fn mul_div(a: Balance, b: Balance, c: Balance) -> Balance {
+            let a: U256 = a.into();
+            let b: U256 = b.into();
+            let c: U256 = c.into();
+            (a * b / c).as_u128()
+        }
+
+        fn mul_div_up(a: Balance, b: Balance, c: Balance) -> Balance {
+            let a: U256 = a.into();
+            let b: U256 = b.into();
+            let c: U256 = c.into();
+            ((a * b + c - 1) / c).as_u128()
+        }
+
+        fn only_owner(&self) {
+            assert_eq!(self.env().caller(), self.owner);
+        }
+    }
+
+    /// Stands in for NSURE and underwrite in the unit tests. Unstaked NSURE is
+    /// released `PENDING_DURATION` after the unstake.
+    #[cfg(test)]
+    mod mock {
+        use super::{AccountId, Balance};
+        use std::cell::RefCell;
+        use std::collections::BTreeMap;
+
+        pub const PENDING_DURATION: u64 = 10;
+
+        #[derive(Default)]
+        pub struct State {
+            // NSURE by account
+            pub balances: BTreeMap<AccountId, Balance>,
+            // NSURE of the vault staked in underwrite, and its pending rewards
+            pub staked: Balance,
+            pub reward: Balance,
+            // unbond requests of the vault, as (amount, unlock_at)
+            pub requests: Vec<(Balance, u64)>,
+        }
+
+        thread_local! {
+            pub static STATE: RefCell<State> = RefCell::new(State::default());
+        }
+
+        pub fn with<R>(f: impl FnOnce(&mut State) -> R) -> R {
+            STATE.with(|state| f(&mut state.borrow_mut()))
+        }
+
+        pub fn balance_of(owner: AccountId) -> Balance {
+            with(|s| s.balances.get(&owner).copied().unwrap_or(0))
+        }
+
+        pub fn set_balance(owner: AccountId, amount: Balance) {
+            with(|s| s.balances.insert(owner, amount));
+        }
+
+        pub fn transfer(from: AccountId, to: AccountId, amount: Balance) {
+            let from_balance = balance_of(from);
+            assert!(from_balance >= amount, "insufficient balance");
+            set_balance(from, from_balance - amount);
+            set_balance(to, balance_of(to) + amount);
+        }
+
+        pub fn stake(vault: AccountId, amount: Balance) {
+            set_balance(vault, balance_of(vault) - amount);
+            with(|s| s.staked += amount);
+        }
+
+        pub fn unstake(amount: Balance) {
+            let unlock_at = ink_env::block_timestamp::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get block timestamp")
+                + PENDING_DURATION;
+            with(|s| {
+                assert!(s.staked >= amount, "unstake: insufficient assets");
+                s.staked -= amount;
+                s.requests.push((amount, unlock_at));
+            })
+        }
+
+        pub fn withdraw_matured(vault: AccountId) -> Balance {
+            let timestamp = ink_env::block_timestamp::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get block timestamp");
+            let amount = with(|s| {
+                let (matured, requests): (Vec<_>, Vec<_>) = s
+                    .requests
+                    .drain(..)
+                    .partition(|&(_, unlock_at)| unlock_at <= timestamp);
+                s.requests = requests;
+                matured.iter().map(|&(amount, _)| amount).sum()
+            });
+            set_balance(vault, balance_of(vault) + amount);
+            amount
+        }
+
+        pub fn claim(vault: AccountId) {
+            let reward = with(|s| core::mem::take(&mut s.reward));
+            set_balance(vault, balance_of(vault) + reward);
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        /// Imports all the definitions from the outer scope so we can use them here.
+        use super::*;
+
+        /// Imports `ink_lang` so we can use `#[ink::test]`.
+        use ink_lang as ink;
+
+        type Accounts = ink_env::test::DefaultAccounts<ink_env::DefaultEnvironment>;
+
+        // a vault owned by alice, who holds 100000 NSURE
+        fn setup() -> (NsureVault, Accounts) {
+            let vault = NsureVault::new(
+                AccountId::from([0x01; 32]),
+                AccountId::from([0x02; 32]),
+                0,
+            );
+
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            mock::set_balance(accounts.alice, 100000);
+            (vault, accounts)
+        }
+
+        fn vault_account() -> AccountId {
+            ink_env::account_id::<ink_env::DefaultEnvironment>().expect("Cannot get contract id")
+        }
+
+        fn advance_blocks(count: u32) {
+            for _ in 0..count {
+                ink_env::test::advance_block::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot advance block");
+            }
+        }
+
+        // the ids of the WithdrawSettled events emitted so far
+        fn settled_ids() -> Vec<u64> {
+            ink_env::test::recorded_events()
+                .filter_map(|event| match <Event as scale::Decode>::decode(&mut &event.data[..]) {
+                    Ok(Event::WithdrawSettled(settled)) => Some(settled.id),
+                    _ => None,
+                })
+                .collect()
+        }
+
+        #[ink::test]
+        fn share_math_test() {
+            assert_eq!(NsureVault::mul_div(100, 50, 200), 25);
+            assert_eq!(NsureVault::mul_div_up(100, 50, 200), 25);
+            assert_eq!(NsureVault::mul_div_up(100, 51, 200), 26);
+            assert_eq!(NsureVault::mul_div(u128::MAX, 2, 4), u128::MAX / 2);
+        }
+
+        #[ink::test]
+        fn net_assets_test() {
+            let mut vault = NsureVault::new(
+                AccountId::from([0x01; 32]),
+                AccountId::from([0x02; 32]),
+                0,
+            );
+
+            // the bounty harvest pays out of the pending rewards is not priced in
+            assert_eq!(vault.net_assets(1000, 200, 10), 1000 + 199 + 10);
+
+            // queued withdrawals are owed their NSURE until it is unstaked
+            vault.queued = 300;
+            assert_eq!(vault.net_assets(1000, 200, 10), 1000 + 199 + 10 - 300);
+
+            vault.total_supply = 1000;
+            assert_eq!(vault.shares_to_burn(100, 500), 200);
+            assert_eq!(vault.shares_to_burn(100, 300), 334);
+        }
+
+        #[ink::test]
+        fn deposit_test() {
+            let (mut vault, accounts) = setup();
+
+            // the first shares are locked in the vault
+            assert_eq!(vault.deposit(10000), 10000 - MINIMUM_SHARES);
+            assert_eq!(vault.balance_of(accounts.alice), 9000);
+            assert_eq!(vault.total_supply(), 10000);
+            assert_eq!(mock::with(|s| s.staked), 10000);
+            assert_eq!(vault.total_assets(), 10000);
+
+            assert_eq!(vault.deposit(5000), 5000);
+            assert_eq!(vault.assets_for(vault.balance_of(accounts.alice)), 14000);
+            assert_eq!(mock::balance_of(accounts.alice), 85000);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "NsureVault: Deposit too small.")]
+        fn deposit_too_small_test() {
+            let (mut vault, _) = setup();
+
+            vault.deposit(MINIMUM_SHARES);
+        }
+
+        #[ink::test]
+        fn donation_test() {
+            let (mut vault, accounts) = setup();
+            mock::set_balance(accounts.alice, 200000);
+            mock::set_balance(accounts.bob, 1000000);
+
+            // a single share, then a donation to inflate its price
+            assert_eq!(vault.deposit(MINIMUM_SHARES + 1), 1);
+            mock::transfer(accounts.bob, vault_account(), 1000000);
+            assert_eq!(vault.total_assets(), 1001001);
+
+            // the next depositor still gets its shares, most of the donation
+            // goes to the locked shares
+            let shares = vault.deposit(100000);
+            assert_eq!(shares, 99);
+            assert_eq!(vault.total_supply(), 1100);
+            assert_eq!(vault.assets_for(shares), 99090);
+            assert_eq!(vault.assets_for(1), 1000);
+        }
+
+        #[ink::test]
+        fn withdraw_queue_test() {
+            let (mut vault, accounts) = setup();
+            advance_blocks(1);
+            vault.deposit(20000);
+
+            assert_eq!(vault.request_withdraw(3000), 0);
+            assert_eq!(vault.request_withdraw(2000), 1);
+            assert_eq!(vault.balance_of(accounts.alice), 14000);
+            assert_eq!(vault.queued(), 5000);
+            assert_eq!(vault.total_assets(), 15000);
+
+            assert_eq!(vault.unstake_batch(u64::MAX, Vec::new()), 5000);
+            assert_eq!(vault.queued(), 0);
+            assert_eq!(mock::with(|s| s.staked), 15000);
+            // requested after the batch, so unstaked by the next one
+            assert_eq!(vault.request_withdraw(1000), 2);
+            assert_eq!(vault.pending_withdrawal(accounts.alice), 6000);
+
+            // nothing is paid before underwrite releases the batch
+            assert_eq!(vault.settle_withdrawals(10), 0);
+            assert_eq!(vault.released(), 0);
+
+            let unlock_at = mock::with(|s| s.requests[0].1);
+            while ink_env::block_timestamp::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get block timestamp")
+                < unlock_at
+            {
+                advance_blocks(1);
+            }
+
+            // paid in request order, up to `max_count`
+            assert_eq!(vault.settle_withdrawals(1), 1);
+            assert_eq!(mock::balance_of(accounts.alice), 83000);
+            assert_eq!(vault.released(), 2000);
+            assert_eq!(vault.withdrawal_queue(), (1, 3));
+
+            // the withdrawal of the next batch waits for it
+            assert_eq!(vault.settle_withdrawals(10), 1);
+            assert_eq!(mock::balance_of(accounts.alice), 85000);
+            assert_eq!(vault.released(), 0);
+            assert_eq!(vault.withdrawal_queue(), (2, 3));
+            assert_eq!(vault.withdrawal(1), None);
+            assert_eq!(vault.withdrawal(2).unwrap().amount, 1000);
+            assert_eq!(vault.pending_withdrawal(accounts.alice), 1000);
+            assert_eq!(settled_ids(), vec![0, 1]);
+        }
+
+        #[ink::test]
+        fn unstake_batch_epoch_test() {
+            let (mut vault, _) = setup();
+            vault.set_epoch_length(10);
+            advance_blocks(1);
+            vault.deposit(20000);
+
+            vault.request_withdraw(1000);
+            vault.unstake_batch(u64::MAX, Vec::new());
+            vault.request_withdraw(1000);
+
+            advance_blocks(10);
+            assert_eq!(vault.unstake_batch(u64::MAX, Vec::new()), 1000);
+            assert_eq!(vault.batch_epoch(), (10, 11));
+            assert_eq!(mock::with(|s| s.requests.len()), 2);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "epoch not over")]
+        fn unstake_batch_twice_test() {
+            let (mut vault, _) = setup();
+            vault.set_epoch_length(10);
+            advance_blocks(1);
+            vault.deposit(20000);
+
+            vault.request_withdraw(1000);
+            vault.unstake_batch(u64::MAX, Vec::new());
+            vault.request_withdraw(1000);
+
+            advance_blocks(9);
+            vault.unstake_batch(u64::MAX, Vec::new());
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "bounty too high")]
+        fn set_bounty_bps_test() {
+            let mut vault = NsureVault::new(
+                AccountId::from([0x01; 32]),
+                AccountId::from([0x02; 32]),
+                0,
+            );
+
+            vault.set_bounty_bps(100);
+            assert_eq!(vault.bounty_bps(), 100);
+            vault.set_bounty_bps(MAX_BOUNTY_BPS + 1);
+        }
+    }
+}
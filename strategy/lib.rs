@@ -0,0 +1,326 @@
+//! strategy defines the interface the capital converter uses to put idle capital to work, a handle calling it on any strategy contract, and a mock strategy contract for tests.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub use self::mock_strategy::MockStrategy;
+use ink_env::{
+    call::{build_call, utils::ReturnType, ExecutionInput, Selector},
+    DefaultEnvironment,
+};
+use ink_lang as ink;
+
+type AccountId = <ink_env::DefaultEnvironment as ink_env::Environment>::AccountId;
+type Balance = <ink_env::DefaultEnvironment as ink_env::Environment>::Balance;
+
+/// A yield strategy holding part of the capital of a converter, its vault.
+///
+/// The vault transfers the underlying token to the strategy before calling `deposit`.
+#[ink::trait_definition]
+pub trait Strategy {
+    /// Registers `amount` of the underlying token sent by the vault.
+    #[ink(message)]
+    fn deposit(&mut self, amount: Balance);
+
+    /// Sends up to `amount` of the underlying token back to the vault and returns what was sent.
+    #[ink(message)]
+    fn withdraw(&mut self, amount: Balance) -> Balance;
+
+    /// Returns the underlying token held by the strategy, including unharvested yield.
+    #[ink(message)]
+    fn total_assets(&self) -> Balance;
+
+    /// Sends the yield above the deposited principal to the vault and returns it.
+    #[ink(message)]
+    fn harvest(&mut self) -> Balance;
+}
+
+/// Selectors of the `Strategy` messages in a contract implementing it,
+/// the first four bytes of `BLAKE2("Strategy::<message>")`.
+pub const DEPOSIT_SELECTOR: [u8; 4] = [0x65, 0x63, 0x86, 0x18];
+pub const WITHDRAW_SELECTOR: [u8; 4] = [0x11, 0xBD, 0x5E, 0xF8];
+pub const TOTAL_ASSETS_SELECTOR: [u8; 4] = [0xB6, 0x29, 0x82, 0x79];
+pub const HARVEST_SELECTOR: [u8; 4] = [0x3A, 0x75, 0x2C, 0xFF];
+
+/// Calls the `Strategy` messages of any contract implementing the trait,
+/// whatever the contract type is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StrategyRef {
+    account: AccountId,
+}
+
+impl StrategyRef {
+    pub fn new(account: AccountId) -> Self {
+        Self { account }
+    }
+
+    pub fn deposit(&mut self, amount: Balance) {
+        let result = build_call::<DefaultEnvironment>()
+            .callee(self.account)
+            .gas_limit(0)
+            .transferred_value(0)
+            .exec_input(ExecutionInput::new(Selector::new(DEPOSIT_SELECTOR)).push_arg(amount))
+            .returns::<()>()
+            .fire();
+        assert!(result.is_ok(), "strategy deposit did not succeed");
+    }
+
+    pub fn withdraw(&mut self, amount: Balance) -> Balance {
+        build_call::<DefaultEnvironment>()
+            .callee(self.account)
+            .gas_limit(0)
+            .transferred_value(0)
+            .exec_input(ExecutionInput::new(Selector::new(WITHDRAW_SELECTOR)).push_arg(amount))
+            .returns::<ReturnType<Balance>>()
+            .fire()
+            .expect("strategy withdraw did not succeed")
+    }
+
+    pub fn total_assets(&self) -> Balance {
+        build_call::<DefaultEnvironment>()
+            .callee(self.account)
+            .gas_limit(0)
+            .transferred_value(0)
+            .exec_input(ExecutionInput::new(Selector::new(TOTAL_ASSETS_SELECTOR)))
+            .returns::<ReturnType<Balance>>()
+            .fire()
+            .expect("strategy total_assets did not succeed")
+    }
+
+    pub fn harvest(&mut self) -> Balance {
+        build_call::<DefaultEnvironment>()
+            .callee(self.account)
+            .gas_limit(0)
+            .transferred_value(0)
+            .exec_input(ExecutionInput::new(Selector::new(HARVEST_SELECTOR)))
+            .returns::<ReturnType<Balance>>()
+            .fire()
+            .expect("strategy harvest did not succeed")
+    }
+}
+
+#[ink::contract]
+mod mock_strategy {
+    use super::Strategy;
+    use erc20::Erc20;
+    use ink_env::call::FromAccountId;
+
+    #[cfg(not(feature = "ink-as-dependency"))]
+    use ink_storage::lazy::Lazy;
+
+    /// A strategy that just holds the underlying token, yield is simulated by
+    /// transferring tokens to it.
+    #[ink(storage)]
+    pub struct MockStrategy {
+        vault: AccountId,
+        token: Lazy<Erc20>,
+        // deposited and not yet withdrawn by the vault
+        principal: Balance,
+    }
+
+    impl MockStrategy {
+        #[ink(constructor)]
+        pub fn new(vault: AccountId, token: AccountId) -> Self {
+            let token: Erc20 = FromAccountId::from_account_id(token);
+            Self {
+                vault,
+                token: Lazy::new(token),
+                principal: 0,
+            }
+        }
+
+        #[ink(message)]
+        pub fn vault(&self) -> AccountId {
+            self.vault
+        }
+
+        #[ink(message)]
+        pub fn principal(&self) -> Balance {
+            self.principal
+        }
+
+        fn only_vault(&self) {
+            assert!(self.env().caller() == self.vault, "not vault");
+        }
+
+        // the token calls go through these, the unit tests swap them for the `mock`
+        // module as they can't reach the token contract off-chain.
+        #[cfg(not(test))]
+        fn token_balance(&self) -> Balance {
+            self.token.balance_of(self.env().account_id())
+        }
+
+        #[cfg(not(test))]
+        fn send_to_vault(&mut self, amount: Balance) -> bool {
+            self.token.transfer(self.vault, amount).is_ok()
+        }
+
+        #[cfg(test)]
+        fn token_balance(&self) -> Balance {
+            mock::balance_of(self.env().account_id())
+        }
+
+        #[cfg(test)]
+        fn send_to_vault(&mut self, amount: Balance) -> bool {
+            mock::transfer(self.env().account_id(), self.vault, amount)
+        }
+    }
+
+    impl Strategy for MockStrategy {
+        #[ink(message)]
+        fn deposit(&mut self, amount: Balance) {
+            self.only_vault();
+            self.principal += amount;
+        }
+
+        #[ink(message)]
+        fn withdraw(&mut self, amount: Balance) -> Balance {
+            self.only_vault();
+            let amount = amount.min(self.total_assets());
+            if amount > 0 {
+                assert!(
+                    self.send_to_vault(amount),
+                    "transfer operation did not succeed"
+                );
+            }
+            self.principal = self.principal.saturating_sub(amount);
+            amount
+        }
+
+        #[ink(message)]
+        fn total_assets(&self) -> Balance {
+            self.token_balance()
+        }
+
+        #[ink(message)]
+        fn harvest(&mut self) -> Balance {
+            self.only_vault();
+            let profit = self.total_assets().saturating_sub(self.principal);
+            if profit > 0 {
+                assert!(
+                    self.send_to_vault(profit),
+                    "transfer operation did not succeed"
+                );
+            }
+            profit
+        }
+    }
+
+    /// Stands in for the underlying token in the unit tests.
+    #[cfg(test)]
+    mod mock {
+        use super::{AccountId, Balance};
+        use std::cell::RefCell;
+        use std::collections::BTreeMap;
+
+        thread_local! {
+            static BALANCES: RefCell<BTreeMap<AccountId, Balance>> = RefCell::new(BTreeMap::new());
+        }
+
+        pub fn balance_of(owner: AccountId) -> Balance {
+            BALANCES.with(|b| b.borrow().get(&owner).copied().unwrap_or(0))
+        }
+
+        pub fn set_balance(owner: AccountId, amount: Balance) {
+            BALANCES.with(|b| b.borrow_mut().insert(owner, amount));
+        }
+
+        pub fn transfer(from: AccountId, to: AccountId, amount: Balance) -> bool {
+            let from_balance = balance_of(from);
+            if from_balance < amount {
+                return false;
+            }
+            set_balance(from, from_balance - amount);
+            set_balance(to, balance_of(to) + amount);
+            true
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        /// Imports all the definitions from the outer scope so we can use them here.
+        use super::*;
+
+        /// Imports `ink_lang` so we can use `#[ink::test]`.
+        use ink_lang as ink;
+
+        #[ink::test]
+        fn deposit_test() {
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+            let mut strategy = MockStrategy::new(accounts.alice, AccountId::from([0x01; 32]));
+
+            strategy.deposit(1000);
+
+            assert_eq!(strategy.principal(), 1000);
+        }
+
+        #[ink::test]
+        fn withdraw_test() {
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+            let mut strategy = MockStrategy::new(accounts.alice, AccountId::from([0x01; 32]));
+            let this = ink_env::account_id::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get contract id");
+            mock::set_balance(this, 1000);
+            strategy.deposit(1000);
+
+            assert_eq!(strategy.withdraw(400), 400);
+            assert_eq!(strategy.principal(), 600);
+            assert_eq!(mock::balance_of(accounts.alice), 400);
+
+            // capped at what the strategy holds after a loss
+            mock::set_balance(this, 500);
+            assert_eq!(strategy.withdraw(1000), 500);
+            assert_eq!(strategy.principal(), 100);
+            assert_eq!(strategy.total_assets(), 0);
+            assert_eq!(mock::balance_of(accounts.alice), 900);
+        }
+
+        #[ink::test]
+        fn total_assets_test() {
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+            let strategy = MockStrategy::new(accounts.alice, AccountId::from([0x01; 32]));
+            let this = ink_env::account_id::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get contract id");
+            assert_eq!(strategy.total_assets(), 0);
+
+            mock::set_balance(this, 1200);
+            assert_eq!(strategy.total_assets(), 1200);
+        }
+
+        #[ink::test]
+        fn harvest_test() {
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+            let mut strategy = MockStrategy::new(accounts.alice, AccountId::from([0x01; 32]));
+            let this = ink_env::account_id::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get contract id");
+            mock::set_balance(this, 1000);
+            strategy.deposit(1000);
+            assert_eq!(strategy.harvest(), 0);
+
+            // yield is simulated by sending tokens to the strategy
+            mock::set_balance(this, 1300);
+            assert_eq!(strategy.harvest(), 300);
+            assert_eq!(strategy.principal(), 1000);
+            assert_eq!(strategy.total_assets(), 1000);
+            assert_eq!(mock::balance_of(accounts.alice), 300);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "not vault")]
+        fn harvest_not_vault_test() {
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+            let mut strategy = MockStrategy::new(accounts.bob, AccountId::from([0x01; 32]));
+
+            strategy.harvest();
+        }
+    }
+}
@@ -0,0 +1,319 @@
+//! wdot wraps native DOT into an ERC-20 token, one WDOT for one DOT, so capital_converter can treat every asset as a token.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+pub use self::wdot::Wdot;
+use ink_lang as ink;
+
+#[ink::contract]
+mod wdot {
+    use ink_prelude::string::String;
+
+    #[cfg(not(feature = "ink-as-dependency"))]
+    use ink_storage::collections::HashMap as StorageHashMap;
+
+    /// Decimals of DOT.
+    pub const DECIMALS: u8 = 10;
+
+    /// Wrapped DOT.
+    #[ink(storage)]
+    pub struct Wdot {
+        /// Total token supply, the DOT held by the contract.
+        total_supply: Balance,
+        /// Mapping from owner to number of owned token.
+        balances: StorageHashMap<AccountId, Balance>,
+        /// Mapping of the token amount which an account is allowed to withdraw
+        /// from another account.
+        allowances: StorageHashMap<(AccountId, AccountId), Balance>,
+    }
+
+    /// Event emitted when a token transfer occurs.
+    #[ink(event)]
+    pub struct Transfer {
+        #[ink(topic)]
+        from: Option<AccountId>,
+        #[ink(topic)]
+        to: Option<AccountId>,
+        value: Balance,
+    }
+
+    /// Event emitted when an approval occurs that `spender` is allowed to withdraw
+    /// up to the amount of `value` tokens from `owner`.
+    #[ink(event)]
+    pub struct Approval {
+        #[ink(topic)]
+        owner: AccountId,
+        #[ink(topic)]
+        spender: AccountId,
+        value: Balance,
+    }
+
+    /// Event emitted when DOT is wrapped.
+    #[ink(event)]
+    pub struct Deposit {
+        #[ink(topic)]
+        to: AccountId,
+        value: Balance,
+    }
+
+    /// Event emitted when WDOT is unwrapped.
+    #[ink(event)]
+    pub struct Withdrawal {
+        #[ink(topic)]
+        from: AccountId,
+        value: Balance,
+    }
+
+    /// The ERC-20 error types.
+    #[derive(Debug, PartialEq, Eq, scale::Encode, scale::Decode)]
+    #[cfg_attr(feature = "std", derive(scale_info::TypeInfo))]
+    pub enum Error {
+        /// Returned if not enough balance to fulfill a request is available.
+        InsufficientBalance,
+        /// Returned if not enough allowance to fulfill a request is available.
+        InsufficientAllowance,
+    }
+
+    /// The ERC-20 result type.
+    pub type Result<T> = core::result::Result<T, Error>;
+
+    impl Wdot {
+        #[ink(constructor)]
+        pub fn new() -> Self {
+            Self {
+                total_supply: 0,
+                balances: StorageHashMap::new(),
+                allowances: StorageHashMap::new(),
+            }
+        }
+
+        /// Wraps the DOT sent along into the same amount of WDOT for the caller.
+        #[ink(message, payable)]
+        pub fn deposit(&mut self) {
+            let caller = self.env().caller();
+            let value = self.env().transferred_balance();
+            let balance = self.balance_of(caller);
+            self.balances.insert(caller, balance + value);
+            self.total_supply += value;
+            self.env().emit_event(Deposit { to: caller, value });
+            self.env().emit_event(Transfer {
+                from: None,
+                to: Some(caller),
+                value,
+            });
+        }
+
+        /// Unwraps `value` WDOT of the caller and sends the DOT to the caller.
+        ///
+        /// Panics if the DOT transfer fails, so the WDOT is not burnt.
+        ///
+        /// # Errors
+        ///
+        /// Returns `InsufficientBalance` error if there are not enough tokens on
+        /// the caller's account balance.
+        #[ink(message)]
+        pub fn withdraw(&mut self, value: Balance) -> Result<()> {
+            let caller = self.env().caller();
+            let balance = self.balance_of(caller);
+            if balance < value {
+                return Err(Error::InsufficientBalance);
+            }
+            self.balances.insert(caller, balance - value);
+            self.total_supply -= value;
+            assert!(
+                self.env().transfer(caller, value).is_ok(),
+                "transfer operation did not succeed"
+            );
+            self.env().emit_event(Withdrawal {
+                from: caller,
+                value,
+            });
+            self.env().emit_event(Transfer {
+                from: Some(caller),
+                to: None,
+                value,
+            });
+            Ok(())
+        }
+
+        /// Returns the total token supply.
+        #[ink(message)]
+        pub fn total_supply(&self) -> Balance {
+            self.total_supply
+        }
+
+        /// Returns the token name.
+        #[ink(message)]
+        pub fn token_name(&self) -> Option<String> {
+            Some(String::from("Wrapped DOT"))
+        }
+
+        /// Returns the token symbol.
+        #[ink(message)]
+        pub fn token_symbol(&self) -> Option<String> {
+            Some(String::from("WDOT"))
+        }
+
+        /// Returns the token decimals.
+        #[ink(message)]
+        pub fn token_decimals(&self) -> Option<u8> {
+            Some(DECIMALS)
+        }
+
+        /// Returns the account balance for the specified `owner`.
+        ///
+        /// Returns `0` if the account is non-existent.
+        #[ink(message)]
+        pub fn balance_of(&self, owner: AccountId) -> Balance {
+            self.balances.get(&owner).copied().unwrap_or(0)
+        }
+
+        /// Returns the amount which `spender` is still allowed to withdraw from `owner`.
+        ///
+        /// Returns `0` if no allowance has been set `0`.
+        #[ink(message)]
+        pub fn allowance(&self, owner: AccountId, spender: AccountId) -> Balance {
+            self.allowances.get(&(owner, spender)).copied().unwrap_or(0)
+        }
+
+        /// Transfers `value` amount of tokens from the caller's account to account `to`.
+        ///
+        /// On success a `Transfer` event is emitted.
+        ///
+        /// # Errors
+        ///
+        /// Returns `InsufficientBalance` error if there are not enough tokens on
+        /// the caller's account balance.
+        #[ink(message)]
+        pub fn transfer(&mut self, to: AccountId, value: Balance) -> Result<()> {
+            let from = self.env().caller();
+            self.transfer_from_to(from, to, value)
+        }
+
+        /// Allows `spender` to withdraw from the caller's account multiple times, up to
+        /// the `value` amount.
+        ///
+        /// If this function is called again it overwrites the current allowance with `value`.
+        ///
+        /// An `Approval` event is emitted.
+        #[ink(message)]
+        pub fn approve(&mut self, spender: AccountId, value: Balance) -> Result<()> {
+            let owner = self.env().caller();
+            self.allowances.insert((owner, spender), value);
+            self.env().emit_event(Approval {
+                owner,
+                spender,
+                value,
+            });
+            Ok(())
+        }
+
+        /// Transfers `value` tokens on the behalf of `from` to the account `to`.
+        ///
+        /// On success a `Transfer` event is emitted.
+        ///
+        /// # Errors
+        ///
+        /// Returns `InsufficientAllowance` error if there are not enough tokens allowed
+        /// for the caller to withdraw from `from`.
+        ///
+        /// Returns `InsufficientBalance` error if there are not enough tokens on
+        /// the the account balance of `from`.
+        #[ink(message)]
+        pub fn transfer_from(
+            &mut self,
+            from: AccountId,
+            to: AccountId,
+            value: Balance,
+        ) -> Result<()> {
+            let caller = self.env().caller();
+            let allowance = self.allowance(from, caller);
+            if allowance < value {
+                return Err(Error::InsufficientAllowance);
+            }
+            self.transfer_from_to(from, to, value)?;
+            self.allowances.insert((from, caller), allowance - value);
+            Ok(())
+        }
+
+        fn transfer_from_to(
+            &mut self,
+            from: AccountId,
+            to: AccountId,
+            value: Balance,
+        ) -> Result<()> {
+            let from_balance = self.balance_of(from);
+            if from_balance < value {
+                return Err(Error::InsufficientBalance);
+            }
+            self.balances.insert(from, from_balance - value);
+            let to_balance = self.balance_of(to);
+            self.balances.insert(to, to_balance + value);
+            self.env().emit_event(Transfer {
+                from: Some(from),
+                to: Some(to),
+                value,
+            });
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        /// Imports all the definitions from the outer scope so we can use them here.
+        use super::*;
+
+        /// Imports `ink_lang` so we can use `#[ink::test]`.
+        use ink_lang as ink;
+
+        #[ink::test]
+        fn deposit_test() {
+            let mut wdot = Wdot::new();
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(1000);
+            wdot.deposit();
+
+            assert_eq!(wdot.balance_of(accounts.alice), 1000);
+            assert_eq!(wdot.total_supply(), 1000);
+        }
+
+        #[ink::test]
+        fn withdraw_test() {
+            let mut wdot = Wdot::new();
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(1000);
+            wdot.deposit();
+            // the off-chain environment doesn't credit the DOT sent along
+            let contract = ink_env::account_id::<ink_env::DefaultEnvironment>()
+                .expect("Cannot get contract id");
+            ink_env::test::set_account_balance::<ink_env::DefaultEnvironment>(contract, 1000)
+                .expect("Cannot set contract balance");
+
+            assert_eq!(wdot.withdraw(400), Ok(()));
+            assert_eq!(wdot.balance_of(accounts.alice), 600);
+            assert_eq!(wdot.total_supply(), 600);
+        }
+
+        #[ink::test]
+        fn transfer_test() {
+            let mut wdot = Wdot::new();
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(1000);
+            wdot.deposit();
+
+            assert_eq!(wdot.transfer(accounts.bob, 100), Ok(()));
+            assert_eq!(wdot.balance_of(accounts.bob), 100);
+            assert_eq!(wdot.balance_of(accounts.alice), 900);
+            assert_eq!(wdot.withdraw(1000), Err(Error::InsufficientBalance));
+        }
+    }
+}
@@ -0,0 +1,456 @@
+//! zap_router wraps DOT, converts it to nDot and stakes it in capital_stake in a single call, and back.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use ink_lang as ink;
+
+#[ink::contract]
+mod zap_router {
+    use capital_converter::CapitalConverter;
+    use capital_stake::CapitalStake;
+    use ink_env::call::FromAccountId;
+    #[cfg(not(test))]
+    use ink_lang::ForwardCallMut;
+    use wdot::Wdot;
+
+    #[cfg(not(feature = "ink-as-dependency"))]
+    use ink_storage::lazy::Lazy;
+
+    #[ink(event)]
+    pub struct ZapIn {
+        #[ink(topic)]
+        user: AccountId,
+        #[ink(topic)]
+        pid: u32,
+        #[ink(topic)]
+        amount: Balance,
+        shares: Balance,
+    }
+
+    #[ink(event)]
+    pub struct ZapOut {
+        #[ink(topic)]
+        user: AccountId,
+        #[ink(topic)]
+        pid: u32,
+        #[ink(topic)]
+        shares: Balance,
+        amount: Balance,
+    }
+
+    #[ink(storage)]
+    pub struct ZapRouter {
+        wdot: Lazy<Wdot>,
+        converter: Lazy<CapitalConverter>,
+        converter_account: AccountId,
+        stake: Lazy<CapitalStake>,
+        stake_account: AccountId,
+    }
+
+    impl ZapRouter {
+        /// The router has to be set as zapper of `converter`, and of `stake` for `zap_out`.
+        #[ink(constructor)]
+        pub fn new(wdot: AccountId, converter: AccountId, stake: AccountId) -> Self {
+            let wdot: Wdot = FromAccountId::from_account_id(wdot);
+            let converter_contract: CapitalConverter = FromAccountId::from_account_id(converter);
+            let stake_contract: CapitalStake = FromAccountId::from_account_id(stake);
+            Self {
+                wdot: Lazy::new(wdot),
+                converter: Lazy::new(converter_contract),
+                converter_account: converter,
+                stake: Lazy::new(stake_contract),
+                stake_account: stake,
+            }
+        }
+
+        #[ink(message)]
+        pub fn converter(&self) -> AccountId {
+            self.converter_account
+        }
+
+        #[ink(message)]
+        pub fn stake(&self) -> AccountId {
+            self.stake_account
+        }
+
+        /// Wraps the DOT sent along, converts it to nDot and stakes it in pool `pid` for the caller.
+        ///
+        /// The deposit limits of the converter apply to the caller.
+        ///
+        /// Returns the nDot staked.
+        #[ink(message, payable)]
+        pub fn zap_in(&mut self, pid: u32) -> Balance {
+            let caller = self.env().caller();
+            let amount = self.env().transferred_balance();
+            assert!(amount > 0, "ZapRouter: Cannot zap 0.");
+
+            self.wrap(amount);
+            let shares = self.convert_for(caller, amount);
+            self.stake_for(pid, shares, caller);
+
+            self.env().emit_event(ZapIn {
+                user: caller,
+                pid,
+                amount,
+                shares,
+            });
+            shares
+        }
+
+        /// Withdraws `shares` nDot of the caller from pool `pid`, exits them and unwraps
+        /// the WDOT, which is sent to the caller as DOT.
+        ///
+        /// The nDot have to be unstaked on the stake contract first and past the pending
+        /// period. The nDot released beyond `shares` are sent back to the caller.
+        ///
+        /// Fails if the converter can't pay the exit right away, as the IOU for it would be
+        /// issued to the router.
+        ///
+        /// Returns the DOT sent.
+        #[ink(message)]
+        pub fn zap_out(&mut self, pid: u32, shares: Balance) -> Balance {
+            let caller = self.env().caller();
+            assert!(shares > 0, "ZapRouter: Cannot zap 0.");
+
+            let released = self.withdraw_for(pid, caller);
+            assert!(released >= shares, "ZapRouter: not enough withdrawable.");
+            if released > shares {
+                self.refund_shares(caller, released - shares);
+            }
+
+            let ious = self.router_ious();
+            let amount = self.exit_for(caller, shares);
+            assert!(self.router_ious() == ious, "ZapRouter: exit not liquid.");
+            self.unwrap(amount);
+            assert!(
+                self.env().transfer(caller, amount).is_ok(),
+                "transfer operation did not succeed"
+            );
+
+            self.env().emit_event(ZapOut {
+                user: caller,
+                pid,
+                shares,
+                amount,
+            });
+            amount
+        }
+
+        // the calls into wdot, the converter and the stake go through the helpers below,
+        // the unit tests swap them for the `mock` module as they can't reach other
+        // contracts off-chain.
+
+        // wraps `amount` DOT sent along into WDOT the converter can pull
+        #[cfg(not(test))]
+        fn wrap(&mut self, amount: Balance) {
+            let wrapped = self
+                .wdot
+                .call_mut()
+                .deposit()
+                .transferred_value(amount)
+                .fire();
+            assert!(wrapped.is_ok(), "deposit operation did not succeed");
+            assert!(
+                self.wdot.approve(self.converter_account, amount).is_ok(),
+                "approve operation did not succeed"
+            );
+        }
+
+        // converts `amount` WDOT for `user` and returns the nDot minted to the router
+        #[cfg(not(test))]
+        fn convert_for(&mut self, user: AccountId, amount: Balance) -> Balance {
+            let self_account = self.env().account_id();
+            let before = self.converter.balance_of(self_account);
+            assert!(
+                self.converter.convert_on_behalf(user, amount).is_ok(),
+                "convert operation did not succeed"
+            );
+            self.converter.balance_of(self_account) - before
+        }
+
+        #[cfg(not(test))]
+        fn stake_for(&mut self, pid: u32, shares: Balance, user: AccountId) {
+            assert!(
+                self.converter.approve(self.stake_account, shares).is_ok(),
+                "approve operation did not succeed"
+            );
+            self.stake.deposit_for(pid, shares, user);
+        }
+
+        #[cfg(not(test))]
+        fn withdraw_for(&mut self, pid: u32, user: AccountId) -> Balance {
+            self.stake.withdraw_for(pid, user)
+        }
+
+        #[cfg(not(test))]
+        fn refund_shares(&mut self, to: AccountId, shares: Balance) {
+            assert!(
+                self.converter.transfer(to, shares).is_ok(),
+                "transfer operation did not succeed"
+            );
+        }
+
+        // exits `shares` nDot for `user` and returns the WDOT received
+        #[cfg(not(test))]
+        fn exit_for(&mut self, user: AccountId, shares: Balance) -> Balance {
+            let self_account = self.env().account_id();
+            let before = self.wdot.balance_of(self_account);
+            assert!(
+                self.converter.exit_on_behalf(user, shares).is_ok(),
+                "exit operation did not succeed"
+            );
+            self.wdot.balance_of(self_account) - before
+        }
+
+        #[cfg(not(test))]
+        fn router_ious(&self) -> Balance {
+            self.converter.iou_balance(self.env().account_id())
+        }
+
+        #[cfg(not(test))]
+        fn unwrap(&mut self, amount: Balance) {
+            assert!(
+                self.wdot.withdraw(amount).is_ok(),
+                "withdraw operation did not succeed"
+            );
+        }
+
+        #[cfg(test)]
+        fn wrap(&mut self, amount: Balance) {
+            mock::wrap(amount)
+        }
+
+        #[cfg(test)]
+        fn convert_for(&mut self, _user: AccountId, amount: Balance) -> Balance {
+            mock::convert(amount)
+        }
+
+        #[cfg(test)]
+        fn stake_for(&mut self, pid: u32, shares: Balance, user: AccountId) {
+            mock::stake(pid, shares, user)
+        }
+
+        #[cfg(test)]
+        fn withdraw_for(&mut self, pid: u32, user: AccountId) -> Balance {
+            mock::withdraw(pid, user)
+        }
+
+        #[cfg(test)]
+        fn refund_shares(&mut self, to: AccountId, shares: Balance) {
+            mock::transfer_shares(to, shares)
+        }
+
+        #[cfg(test)]
+        fn exit_for(&mut self, _user: AccountId, shares: Balance) -> Balance {
+            mock::exit(shares)
+        }
+
+        #[cfg(test)]
+        fn router_ious(&self) -> Balance {
+            mock::with(|s| s.ious)
+        }
+
+        #[cfg(test)]
+        fn unwrap(&mut self, amount: Balance) {
+            mock::unwrap(self.env().account_id(), amount)
+        }
+    }
+
+    /// Stands in for wdot, the converter and the stake in the unit tests. nDot
+    /// converts 1:1 and the converter pays exits from its `liquid` WDOT.
+    #[cfg(test)]
+    mod mock {
+        use super::{AccountId, Balance};
+        use std::cell::RefCell;
+        use std::collections::BTreeMap;
+
+        #[derive(Default)]
+        pub struct State {
+            // WDOT and nDot held by the router
+            pub wdot: Balance,
+            pub ndot: Balance,
+            // nDot sent back to users
+            pub refunds: BTreeMap<AccountId, Balance>,
+            // staked nDot by pool and user
+            pub staked: BTreeMap<(u32, AccountId), Balance>,
+            // nDot past the pending period by pool and user
+            pub withdrawable: BTreeMap<(u32, AccountId), Balance>,
+            // WDOT the converter can pay right away, the rest of an exit is an IOU
+            pub liquid: Balance,
+            // IOUs issued to the router
+            pub ious: Balance,
+        }
+
+        thread_local! {
+            pub static STATE: RefCell<State> = RefCell::new(State::default());
+        }
+
+        pub fn with<R>(f: impl FnOnce(&mut State) -> R) -> R {
+            STATE.with(|state| f(&mut state.borrow_mut()))
+        }
+
+        pub fn wrap(amount: Balance) {
+            with(|s| s.wdot += amount)
+        }
+
+        pub fn convert(amount: Balance) -> Balance {
+            with(|s| {
+                s.wdot -= amount;
+                s.liquid += amount;
+                s.ndot += amount;
+            });
+            amount
+        }
+
+        pub fn stake(pid: u32, shares: Balance, user: AccountId) {
+            with(|s| {
+                s.ndot -= shares;
+                *s.staked.entry((pid, user)).or_insert(0) += shares;
+            })
+        }
+
+        pub fn withdraw(pid: u32, user: AccountId) -> Balance {
+            let amount = with(|s| s.withdrawable.remove(&(pid, user)).unwrap_or(0));
+            assert!(amount > 0, "still pending");
+            with(|s| s.ndot += amount);
+            amount
+        }
+
+        pub fn transfer_shares(to: AccountId, shares: Balance) {
+            with(|s| {
+                s.ndot -= shares;
+                *s.refunds.entry(to).or_insert(0) += shares;
+            })
+        }
+
+        pub fn exit(shares: Balance) -> Balance {
+            with(|s| {
+                let paid = shares.min(s.liquid);
+                s.ndot -= shares;
+                s.liquid -= paid;
+                s.ious += shares - paid;
+                s.wdot += paid;
+                paid
+            })
+        }
+
+        pub fn unwrap(router: AccountId, amount: Balance) {
+            with(|s| s.wdot -= amount);
+            let balance = ink_env::test::get_account_balance::<ink_env::DefaultEnvironment>(router)
+                .expect("Cannot get contract balance");
+            ink_env::test::set_account_balance::<ink_env::DefaultEnvironment>(
+                router,
+                balance + amount,
+            )
+            .expect("Cannot set contract balance");
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        /// Imports all the definitions from the outer scope so we can use them here.
+        use super::*;
+
+        /// Imports `ink_lang` so we can use `#[ink::test]`.
+        use ink_lang as ink;
+
+        fn router() -> ZapRouter {
+            ZapRouter::new(
+                AccountId::from([0x01; 32]),
+                AccountId::from([0x02; 32]),
+                AccountId::from([0x03; 32]),
+            )
+        }
+
+        #[ink::test]
+        fn zap_in_test() {
+            let mut zap_router = router();
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            ink_env::test::set_value_transferred::<ink_env::DefaultEnvironment>(1000);
+            assert_eq!(zap_router.zap_in(2), 1000);
+            mock::with(|s| {
+                assert_eq!(s.staked.get(&(2, accounts.alice)), Some(&1000));
+                assert_eq!(s.ndot, 0);
+                assert_eq!(s.wdot, 0);
+            });
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "ZapRouter: Cannot zap 0.")]
+        fn zap_in_zero_test() {
+            let mut zap_router = router();
+
+            zap_router.zap_in(0);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "ZapRouter: Cannot zap 0.")]
+        fn zap_out_zero_test() {
+            let mut zap_router = router();
+
+            zap_router.zap_out(0, 0);
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "ZapRouter: not enough withdrawable.")]
+        fn zap_out_not_enough_withdrawable_test() {
+            let mut zap_router = router();
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            mock::with(|s| {
+                s.withdrawable.insert((0, accounts.alice), 500);
+                s.liquid = 1000;
+            });
+            zap_router.zap_out(0, 600);
+        }
+
+        #[ink::test]
+        fn zap_out_refund_test() {
+            let mut zap_router = router();
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+            let before =
+                ink_env::test::get_account_balance::<ink_env::DefaultEnvironment>(accounts.alice)
+                    .expect("Cannot get account balance");
+
+            mock::with(|s| {
+                s.withdrawable.insert((0, accounts.alice), 1000);
+                s.liquid = 1000;
+            });
+            assert_eq!(zap_router.zap_out(0, 600), 600);
+            // the nDot released beyond the zapped shares go back to the caller
+            mock::with(|s| {
+                assert_eq!(s.refunds.get(&accounts.alice), Some(&400));
+                assert_eq!(s.ndot, 0);
+                assert_eq!(s.wdot, 0);
+            });
+            assert_eq!(
+                ink_env::test::get_account_balance::<ink_env::DefaultEnvironment>(accounts.alice)
+                    .expect("Cannot get account balance"),
+                before + 600
+            );
+        }
+
+        #[ink::test]
+        #[should_panic(expected = "ZapRouter: exit not liquid.")]
+        fn zap_out_iou_test() {
+            let mut zap_router = router();
+            let accounts =
+                ink_env::test::default_accounts::<ink_env::DefaultEnvironment>()
+                    .expect("Cannot get accounts");
+
+            // the converter only holds part of the exit, the rest would be an IOU
+            mock::with(|s| {
+                s.withdrawable.insert((0, accounts.alice), 600);
+                s.liquid = 300;
+            });
+            zap_router.zap_out(0, 600);
+        }
+    }
+}